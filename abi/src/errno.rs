@@ -0,0 +1,63 @@
+/// Errors returned by system calls. Values follow the usual Unix numbering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum Errno {
+    EPERM = 1,         // Operation not permitted
+    ENOENT = 2,        // No such file or directory
+    ESRCH = 3,         // No such process
+    EINTR = 4,         // Interrupted system call
+    EIO = 5,           // I/O error
+    E2BIG = 7,         // Argument list too long
+    ENOEXEC = 8,       // Exec format error
+    ECHILD = 10,       // No child processes
+    EAGAIN = 11,       // Try again
+    ENOMEM = 12,       // Out of memory
+    EFAULT = 14,       // Bad address
+    EINVAL = 22,       // Invalid argument
+    ENAMETOOLONG = 36, // File name too long
+    ENOSYS = 38,       // Invalid system call number
+}
+
+/// Highest error number. Return values in [-MAX_ERRNO, -1] are errors, anything else is a value.
+pub const MAX_ERRNO: usize = 4095;
+
+impl Errno {
+    /// Value placed in eax when a system call fails: the error number, negated
+    pub fn to_return_value(self) -> usize {
+        (self as usize).wrapping_neg()
+    }
+
+    pub fn from_errno(errno: usize) -> Option<Self> {
+        let error = match errno {
+            1 => Errno::EPERM,
+            2 => Errno::ENOENT,
+            3 => Errno::ESRCH,
+            4 => Errno::EINTR,
+            5 => Errno::EIO,
+            7 => Errno::E2BIG,
+            8 => Errno::ENOEXEC,
+            10 => Errno::ECHILD,
+            11 => Errno::EAGAIN,
+            12 => Errno::ENOMEM,
+            14 => Errno::EFAULT,
+            22 => Errno::EINVAL,
+            36 => Errno::ENAMETOOLONG,
+            38 => Errno::ENOSYS,
+            _ => return None,
+        };
+
+        Some(error)
+    }
+
+    /// Decode the value returned in eax by a system call. Unknown error numbers are reported
+    /// as EINVAL.
+    pub fn from_return_value(value: usize) -> Result<usize, Errno> {
+        match value.wrapping_neg() {
+            errno @ 1..=MAX_ERRNO => Err(Errno::from_errno(errno).unwrap_or(Errno::EINVAL)),
+            _ => Ok(value),
+        }
+    }
+}
+
+/// Result of a system call, as seen by both the Kernel and the user space
+pub type SystemCallResult = Result<usize, Errno>;
@@ -0,0 +1,14 @@
+/// System calls are issued with `int SYSCALL_VECTOR`. The system call number goes in eax, and
+/// the arguments in edi, esi, edx and ecx (in this order). The result comes back in eax: either
+/// a value, or a negated Errno (see Errno::to_return_value).
+pub const SYSCALL_VECTOR: u8 = 64;
+
+pub const NUM_SYS_CALLS: usize = 6;
+
+/// System Call Numbers
+pub const PRINT_TRAPFRAME_SYSCALL: usize = 0;
+pub const FORK_SYSCALL: usize = 1;
+pub const EXEC_SYSCALL: usize = 2;
+pub const EXIT_SYSCALL: usize = 3;
+pub const WAIT_SYSCALL: usize = 4;
+pub const WAITPID_SYSCALL: usize = 5;
@@ -11,9 +11,6 @@ _start_16:
     ; Clear interrupts
     cli
 
-    mov ax, 0xe820
-    call do_e820
-    
     ; Zero out segment registers
     xor ax, ax
     mov ds, ax
@@ -22,6 +19,10 @@ _start_16:
     mov fs, ax
     mov gs, ax
 
+    ; Query the BIOS memory map (needs ES = 0, as entries are written to es:di)
+    mov ax, 0xe820
+    call do_e820
+
     ; Clear direction bits
     cld
 
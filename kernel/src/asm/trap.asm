@@ -1,15 +1,28 @@
-; Pop all registers from the trap frame, skip elements, and then return to the start of
-; the process being called.
+; Entry stubs for every interrupt vector. Each stub makes the stack look the same (pushing a
+; dummy error code when the CPU does not provide one), pushes its vector number, and jumps to
+; the common trap entry, which builds the rest of the trap frame and calls the dispatcher.
 
-extern user_interrupt_handler
+extern trap_handler
 
 global trap_enter
 global trap_return
+global trap_vectors
 
-trap_enter:
+; Generate vector0 ... vector255. Only exceptions 8, 10-14, 17, 21, 29 and 30 have their
+; error code pushed by the CPU.
+%assign i 0
+%rep 256
+global vector%+i
+vector%+i:
+%if !(i == 8 || (i >= 10 && i <= 14) || i == 17 || i == 21 || i == 29 || i == 30)
     push 0  ; Error Code
-    push 64 ; General User Trap Number
+%endif
+    push i  ; Trap Number
+    jmp trap_enter
+%assign i i+1
+%endrep
 
+trap_enter:
     push dword ds
     push dword es
     push dword fs
@@ -19,11 +32,15 @@ trap_enter:
     mov ax, 0x10
     mov ds, ax
     mov es, ax
+    mov ax, 0x30 ; Per-CPU Data Segment, as user code may have changed GS
+    mov gs, ax
 
     push esp
-    call user_interrupt_handler
+    call trap_handler
     add esp, 4
 
+; Pop all registers from the trap frame, skip elements, and then return to the start of
+; the process being called.
 trap_return:
     popa
     pop dword gs
@@ -31,4 +48,13 @@ trap_return:
     pop dword es
     pop dword ds
     add esp, 0x8 ; Skip Trap Number and Error Code
-    iret
\ No newline at end of file
+    iret
+
+; Addresses of every entry stub, indexed by vector. Used to fill the IDT.
+section .rodata
+trap_vectors:
+%assign i 0
+%rep 256
+    dd vector%+i
+%assign i i+1
+%endrep
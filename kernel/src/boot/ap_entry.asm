@@ -0,0 +1,89 @@
+; Entry point of the Application Processors (APs). The BSP copies this code to AP_TRAMPOLINE,
+; below 1MiB, and wakes each AP up with a STARTUP IPI pointing there. APs start in real mode, so
+; here we switch to protected mode, enable paging with the boot page directory (see entry.asm),
+; move over to the Kernel page directory, and finally call into Rust on the stack the BSP set up
+; for us. The BSP leaves those parameters right below AP_TRAMPOLINE:
+;   AP_TRAMPOLINE - 4:  Top of the Kernel stack
+;   AP_TRAMPOLINE - 8:  Entry function
+;   AP_TRAMPOLINE - 12: Boot page directory (physical address)
+;   AP_TRAMPOLINE - 16: Kernel page directory (physical address)
+
+%define AP_TRAMPOLINE 0x7000
+%define KERNEL_BASE 0x80000000
+
+; This code runs from AP_TRAMPOLINE, not from where it was linked
+%define AP_ADDRESS(label) (label - ap_trampoline_start + AP_TRAMPOLINE)
+
+global ap_trampoline_start
+global ap_trampoline_end
+
+section .text
+align 16
+bits 16
+ap_trampoline_start:
+    cli
+    cld
+
+    xor ax, ax
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+
+    lgdt [AP_ADDRESS(ap_gdt_pointer)]
+
+    mov eax, cr0
+    or eax, 1 ; Protection Enable
+    mov cr0, eax
+
+    jmp dword 0x08:AP_ADDRESS(ap_protected_mode)
+
+bits 32
+ap_protected_mode:
+    mov ax, 0x10
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+    xor ax, ax
+    mov fs, ax
+    mov gs, ax
+
+    ; Enable Size Extension (4MB per page), used by the boot page directory
+    mov eax, cr4
+    or eax, 0x00000010
+    mov cr4, eax
+
+    mov eax, [AP_TRAMPOLINE - 12]
+    mov cr3, eax
+
+    mov eax, cr0
+    or eax, (1 << 31) | (1 << 16) ; Paging Enable, Write Protect
+    mov cr0, eax
+
+    ; Jump to the higher half, which the Kernel page directory maps as well
+    mov eax, KERNEL_BASE + AP_ADDRESS(ap_higher_half)
+    jmp eax
+
+ap_higher_half:
+    mov eax, [KERNEL_BASE + AP_TRAMPOLINE - 16]
+    mov cr3, eax
+
+    mov esp, [KERNEL_BASE + AP_TRAMPOLINE - 4]
+    call [KERNEL_BASE + AP_TRAMPOLINE - 8]
+
+    ; The entry function never returns
+.halt:
+    hlt
+    jmp .halt
+
+; Flat segments, only used until the AP loads its own GDT
+align 8
+ap_gdt:
+    dq 0                  ; Null Segment
+    dq 0x00CF9A000000FFFF ; Kernel Code Segment
+    dq 0x00CF92000000FFFF ; Kernel Data Segment
+
+ap_gdt_pointer:
+    dw ap_gdt_pointer - ap_gdt - 1
+    dd AP_ADDRESS(ap_gdt)
+
+ap_trampoline_end:
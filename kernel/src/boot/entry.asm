@@ -9,6 +9,9 @@ global entry
 global kernel_start
 kernel_start: equ entry - 0x80000000
 
+; Application Processors also use the initial page directory (see ap_entry.asm)
+global boot_page_dir
+
 section .text.kernel
 bits 32
 entry:
@@ -47,6 +50,7 @@ enable_paging:
     ret
 
 align 4096 ; Ensures page alignment
+boot_page_dir:
 pd_table:
     dd 0x83 ; Allows access to [0, 4MiB) section of memory
     resd 511
@@ -1,6 +1,7 @@
 use core::fmt;
 use lazy_static::lazy_static;
-use spin::Mutex;
+
+use crate::threading::defs::SpinLock;
 
 use super::uart::uart_put_char;
 
@@ -26,5 +27,5 @@ impl Console {
 }
 
 lazy_static! {
-    pub static ref CONSOLE: Mutex<Console> = Mutex::new(Console {});
+    pub static ref CONSOLE: SpinLock<Console> = SpinLock::new(Console {});
 }
@@ -1,7 +1,7 @@
 use core::fmt;
 use core::fmt::Write;
 
-use super::console::CONSOLE;
+use super::console::{Console, CONSOLE};
 use super::uart;
 
 /* ************ Macros ************ */
@@ -25,3 +25,8 @@ pub fn debug_init() {
 pub fn _print(args: fmt::Arguments) {
     CONSOLE.lock().write_fmt(args).unwrap();
 }
+
+// Prints without taking the console lock, which the panicking code may hold
+pub fn _panic_print(args: fmt::Arguments) {
+    let _ = Console.write_fmt(args);
+}
@@ -1,6 +1,6 @@
-use alloc::boxed::Box;
+use alloc::collections::VecDeque;
 use core::sync::atomic::AtomicBool;
-use spin::Mutex;
+use crate::threading::defs::SpinLock;
 use crate::fs::defs::Buf;
 
 pub const COM1: u16 = 0x3F8; // Base port address for first serial communication port
@@ -25,7 +25,25 @@ pub const B_SIZE: usize = 512; // Size of one block
 
 pub const FS_SIZE: usize = 1000; // Size of file system in blocks
 
+/// Pending disk requests, the first one being the one the disk is working on. Buffers belong
+/// to the processes waiting for them, which sleep until the request is done.
+pub struct IdeQueue {
+    pub requests: VecDeque<*mut Buf>,
+}
+
 pub struct Ide {
-    pub idequeue: Mutex<Option<Box<Buf>>>,
+    pub idequeue: SpinLock<IdeQueue>,
     pub havedisk1: AtomicBool,
 }
+
+pub const PIT_CHANNEL_0: u16 = 0x40; // Channel 0 data port, wired to IRQ 0
+pub const PIT_CHANNEL_2: u16 = 0x42; // Channel 2 data port, gated through PIT_GATE
+pub const PIT_COMMAND: u16 = 0x43; // Mode/Command register
+pub const PIT_MODE_RATE_GENERATOR: u8 = 0x34; // Channel 0, lobyte/hibyte access, mode 2
+pub const PIT_MODE_TERMINAL_COUNT: u8 = 0xB0; // Channel 2, lobyte/hibyte access, mode 0
+pub const PIT_GATE: u16 = 0x61; // Channel 2 gate (bit 0), speaker (bit 1) and output (bit 5)
+pub const PIT_GATE_ENABLE: u8 = 1 << 0;
+pub const PIT_SPEAKER_ENABLE: u8 = 1 << 1;
+pub const PIT_CHANNEL_2_OUTPUT: u8 = 1 << 5;
+pub const PIT_BASE_FREQUENCY: usize = 1193182; // Frequency (Hz) of the PIT oscillator
+pub const TIMER_FREQUENCY: usize = 100; // Timer interrupts per second
@@ -1,9 +1,12 @@
 use crate::x86::helpers::{inb, outb, inw, outw};
 use crate::fs::defs::Buf;
+use crate::interrupts::{apic::InterruptIndex, irq::register_irq};
+use crate::scheduler::{defs::process::TrapFrame, scheduler::{sleep, wakeup}};
 use crate::println;
-use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use core::ptr;
 use core::sync::atomic::{AtomicBool, Ordering};
-use spin::Mutex;
+use crate::threading::defs::SpinLock;
 use lazy_static::lazy_static;
 use super::defs::*;
 
@@ -11,7 +14,9 @@ impl Ide {
     // Constructor for Ide Struct
     pub fn new() -> Ide {
         Ide {
-            idequeue: Mutex::new(None),
+            idequeue: SpinLock::new(IdeQueue {
+                requests: VecDeque::new(),
+            }),
             havedisk1: AtomicBool::new(false),
         }
     }
@@ -33,7 +38,7 @@ impl Ide {
     }
     
     // Initializes IDE device and checks that disk 1 is present
-    pub fn ideinit(&mut self) {
+    pub fn ideinit(&self) {
         self.idewait(false).unwrap();
 
         // Check if disk 1 is present
@@ -49,8 +54,9 @@ impl Ide {
         outb(0x1f6, 0xe0 | (0 << 4));
     }
 
-    // Starts processing requests and sends necessary data to the IDE device
-    fn idestart(&mut self, b: &mut Buf) {
+    // Issues the request for the buffer to the IDE device. The device raises an interrupt once
+    // it is done. Must be called with the queue locked.
+    fn idestart(&self, b: &Buf) {
         if b.blockno >= FS_SIZE {
             panic!("incorrect blockno");
         }
@@ -93,21 +99,21 @@ impl Ide {
         } else {
             outb(0x1f7, read_cmd);
         }
-
-        self.idewait(false).unwrap();
-        self.ideintr(b);
     }
 
-    // Interrupt handler. Currently using busy waiting but can be edited by deleting the final
-    // while loop and uncommenting the wakeup call.
-    pub fn ideintr(&mut self, b: &mut Buf) {
-        let idewait_result = self.idewait(true).is_ok();
-
+    // Interrupt handler. Finishes the request at the head of the queue, wakes up the process
+    // waiting for it and starts the next request.
+    pub fn ideintr(&self) {
         let mut queue = self.idequeue.lock();
-        let mut next_buf_option = None;
-        
+
+        // Nothing was requested, the interrupt is not for us
+        let b = match queue.requests.pop_front() {
+            Some(b) => unsafe { &mut *b },
+            None => return,
+        };
+
         // If dirty bit is not set, it must be a read
-        if b.flags & B_DIRTY == 0 && idewait_result {
+        if b.flags & B_DIRTY == 0 && self.idewait(true).is_ok() {
             for i in (0..B_SIZE).step_by(4) {
                 unsafe {
                     let data = inw(0x1f0);
@@ -118,73 +124,58 @@ impl Ide {
 
         b.flags |= B_VALID; // Set valid bit
         b.flags &= !B_DIRTY; // Unset dirty bit
-        
-        // In a real scheduler, you would wake up the process waiting for this buf here.
-        // wakeup(b);
-
-        // Set queue to next val
-        *queue = b.qnext.clone();
+        wakeup(b as *mut Buf as usize);
 
         // Start disk on next buf in queue
-        if let Some(next_buf) = queue.take() {
-            next_buf_option = Some(next_buf);
+        if let Some(&next) = queue.requests.front() {
+            self.idestart(unsafe { &*next });
         }
-        
-        drop(queue); // Explicitly drop the lock to release the borrow of self
-        
-        if let Some(mut next_buf) = next_buf_option {
-            self.idestart(&mut next_buf);
-        }
-
-        // Busy-wait for the operation to complete
-        while self.idewait(true).is_err() {}
     }
-    
-    // Not working version that compiles
-    pub fn iderw(&mut self, b: &mut Buf) {
+
+    // Queues a request to read (or write, if dirty) the buffer, and sleeps until the interrupt
+    // handler finished it
+    pub fn iderw(&self, b: &mut Buf) {
         if (b.flags & (B_VALID | B_DIRTY)) == B_VALID {
             panic!("iderw: nothing to do");
         }
         if b.dev != 0 && !self.havedisk1.load(Ordering::SeqCst) {
             panic!("iderw: ide disk 1 not present");
         }
-        
-        // Acquire lock to queue
-        let mut queue = self.idequeue.lock();
-        let mut start_disk = false;
 
-        // Check if the buffer will be the only value in the queue 
-        if queue.is_none() {
-            start_disk = true;
-        }
+        let buf = b as *mut Buf;
 
-        // Append b to back of queue
-        b.qnext = None;
-        let mut pp = &mut *queue;
-        while let Some(p) = pp {
-            pp = &mut p.qnext;
-        }
-        *pp = Some(Box::new(b.clone()));
+        // Acquire lock to queue, and append b to it
+        let mut queue = self.idequeue.lock();
+        queue.requests.push_back(buf);
 
-        drop(queue); // Explicitly drop the lock to release the borrow of self
-        
-        if start_disk {
+        // Start disk if b is the only request, otherwise it starts once the previous ones are done
+        if queue.requests.len() == 1 {
             self.idestart(b);
         }
-        
-        // Wait for request to finish (if using a real scheduler).
-        // sleep() called here once scheduler impl
-        // while !done_flag.load(Ordering::SeqCst) {}
-         
-        while b.flags & (B_VALID | B_DIRTY) != B_VALID {}
+
+        // Wait for request to finish. The flags are updated by the interrupt handler, with the
+        // queue locked.
+        while unsafe { ptr::read_volatile(&(*buf).flags) } & (B_VALID | B_DIRTY) != B_VALID {
+            queue = sleep(buf as usize, queue);
+        }
     }
 }
 
+// Requests only ever move between the queue and the interrupt handler, with the queue locked
+unsafe impl Send for IdeQueue {}
+
 lazy_static! {
-    pub static ref GLOBAL_IDE: Mutex<Ide> = Mutex::new(Ide::new());
+    pub static ref GLOBAL_IDE: Ide = Ide::new();
+}
+
+// IRQ 14 handler, raised once the disk finished a request
+fn primary_disk_access(_trapframe: &mut TrapFrame) {
+    GLOBAL_IDE.ideintr();
 }
 
 pub fn setup_ide() {
-    GLOBAL_IDE.lock().ideinit();
+    GLOBAL_IDE.ideinit();
+    register_irq(InterruptIndex::PrimaryATAHardDisk, primary_disk_access)
+        .expect("[FATAL] Disk IRQ Unavailable");
     println!("[KERNEL] Disk Initialized");
 }
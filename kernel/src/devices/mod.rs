@@ -3,3 +3,4 @@ pub mod debug;
 pub mod defs;
 pub mod uart;
 pub mod ide;
+pub mod timer;
@@ -0,0 +1,149 @@
+/// Programmable Interval Timer (8253/8254). The PIT oscillator runs at PIT_BASE_FREQUENCY and
+/// channel 0 divides it down to fire IRQ 0 at a chosen frequency. Every interrupt is a tick of
+/// the Kernel clock, which is used to keep track of uptime and to put callers to sleep.
+/// When the machine has APICs, ticks come from the Local APIC timer instead, and the PIT is only
+/// used (through channel 2) to calibrate it.
+/// More information can be found here https://wiki.osdev.org/Programmable_Interval_Timer.
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{
+    interrupts::{
+        apic::{apic_enabled, InterruptIndex},
+        interrupt_handlers::from_user_mode,
+        irq::register_irq,
+        lapic::set_timer_frequency,
+    },
+    println,
+    scheduler::{
+        defs::process::TrapFrame,
+        scheduler::{preempt, sleep, wakeup},
+    },
+    smp::cpu::is_boot_cpu,
+    threading::defs::SpinLock,
+    x86::helpers::{inb, outb},
+};
+
+use super::defs::{
+    PIT_BASE_FREQUENCY, PIT_CHANNEL_0, PIT_CHANNEL_2, PIT_CHANNEL_2_OUTPUT, PIT_COMMAND, PIT_GATE,
+    PIT_GATE_ENABLE, PIT_MODE_RATE_GENERATOR, PIT_MODE_TERMINAL_COUNT, PIT_SPEAKER_ENABLE,
+    TIMER_FREQUENCY,
+};
+
+/// Ticks since the timer was set up. Only ever grows.
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+
+/// Held while ticking and while checking the clock before sleeping, so no tick goes unnoticed
+static TICKS_LOCK: SpinLock<()> = SpinLock::new(());
+
+/// Frequency (in Hz) the PIT is currently programmed to
+static FREQUENCY: AtomicUsize = AtomicUsize::new(TIMER_FREQUENCY);
+
+/// Program channel 0 of the PIT to interrupt at the given frequency. The divisor is 16 bits
+/// wide, so frequencies are clamped between ~19 Hz and the PIT base frequency.
+fn set_pit_frequency(frequency: usize) -> usize {
+    let divisor = (PIT_BASE_FREQUENCY / frequency.max(1)).clamp(1, 0xFFFF);
+
+    outb(PIT_COMMAND, PIT_MODE_RATE_GENERATOR);
+    outb(PIT_CHANNEL_0, (divisor & 0xFF) as u8);
+    outb(PIT_CHANNEL_0, (divisor >> 8) as u8);
+
+    PIT_BASE_FREQUENCY / divisor
+}
+
+/// Set the tick frequency of whichever timer drives the Kernel clock
+pub fn set_frequency(frequency: usize) {
+    let frequency = match apic_enabled() {
+        true => set_timer_frequency(frequency),
+        false => set_pit_frequency(frequency),
+    };
+
+    FREQUENCY.store(frequency, Ordering::SeqCst);
+}
+
+/// Busy wait for the given number of milliseconds (up to ~54) with channel 2 of the PIT, which
+/// raises no interrupts. Works before interrupts are enabled.
+pub fn pit_delay_ms(milliseconds: usize) {
+    let count = (PIT_BASE_FREQUENCY * milliseconds / 1000).clamp(1, 0xFFFF);
+
+    // Keep the speaker off, and hold the gate low while programming the counter
+    let gate = inb(PIT_GATE) & !(PIT_GATE_ENABLE | PIT_SPEAKER_ENABLE);
+    outb(PIT_GATE, gate);
+
+    outb(PIT_COMMAND, PIT_MODE_TERMINAL_COUNT);
+    outb(PIT_CHANNEL_2, (count & 0xFF) as u8);
+    outb(PIT_CHANNEL_2, (count >> 8) as u8);
+
+    // In mode 0 the output stays low from the command until the count reaches zero. Rising
+    // the gate starts the count.
+    outb(PIT_GATE, gate | PIT_GATE_ENABLE);
+
+    while inb(PIT_GATE) & PIT_CHANNEL_2_OUTPUT == 0 {}
+
+    outb(PIT_GATE, gate);
+}
+
+pub fn frequency() -> usize {
+    FREQUENCY.load(Ordering::SeqCst)
+}
+
+pub fn ticks() -> usize {
+    TICKS.load(Ordering::SeqCst)
+}
+
+/// Milliseconds since the timer was set up. Zero while the timer has no frequency.
+pub fn uptime_ms() -> usize {
+    match frequency() {
+        0 => 0,
+        frequency => ticks() * 1000 / frequency,
+    }
+}
+
+/// Sleeping channel of callers waiting for ticks
+fn ticks_channel() -> usize {
+    &TICKS as *const AtomicUsize as usize
+}
+
+/// Account a tick, waking up everyone waiting for one. Called from the timer interrupt.
+pub fn tick() {
+    let _ticks = TICKS_LOCK.lock();
+
+    TICKS.fetch_add(1, Ordering::SeqCst);
+    wakeup(ticks_channel());
+}
+
+/// IRQ 0 handler (Local APIC timer included)
+fn timer_interrupt(trapframe: &mut TrapFrame) {
+    // Every processor has its own timer, but only the BSP keeps the Kernel clock
+    if is_boot_cpu() {
+        tick();
+    }
+
+    // Only user processes are preempted, the Kernel always runs until it gives up the CPU
+    if from_user_mode(trapframe) {
+        preempt();
+    }
+}
+
+/// Block the caller for (at least) the given number of ticks
+pub fn sleep_ticks(count: usize) {
+    let mut ticks_lock = TICKS_LOCK.lock();
+    let target = ticks() + count;
+
+    while ticks() < target {
+        ticks_lock = sleep(ticks_channel(), ticks_lock);
+    }
+}
+
+/// Block the caller for (at least) the given number of milliseconds
+pub fn sleep_ms(milliseconds: usize) {
+    let frequency = frequency();
+    sleep_ticks((milliseconds * frequency).div_ceil(1000));
+}
+
+/// Program the timer to TIMER_FREQUENCY. Should run before interrupts are enabled, and after
+/// the interrupt controller is set up.
+pub fn setup_timer() {
+    set_frequency(TIMER_FREQUENCY);
+    register_irq(InterruptIndex::Timer, timer_interrupt).expect("[FATAL] Timer IRQ Unavailable");
+    println!("[KERNEL] Timer Initialized: {} Hz", frequency());
+}
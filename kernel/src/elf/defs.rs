@@ -0,0 +1,66 @@
+use bitflags::bitflags;
+
+/// ELF Identification
+pub const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+pub const ELF_CLASS_32: u8 = 1;
+pub const ELF_DATA_LSB: u8 = 1; // Little Endian
+pub const ELF_VERSION_CURRENT: u8 = 1;
+
+/// ELF Types and Machines
+pub const ELF_TYPE_EXECUTABLE: u16 = 2;
+pub const ELF_MACHINE_386: u16 = 3;
+
+/// Program Header Types
+pub const PROGRAM_TYPE_NULL: u32 = 0;
+pub const PROGRAM_TYPE_LOAD: u32 = 1;
+
+/// ELF32 file header, found at the very beginning of the executable.
+/// See more at https://wiki.osdev.org/ELF
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ElfHeader {
+    pub ident: [u8; 16],    // Magic, Class, Data Encoding, Version, Padding
+    pub kind: u16,          // Relocatable, Executable, Shared, Core
+    pub machine: u16,       // Target Instruction Set
+    pub version: u32,       // ELF Version
+    pub entry: u32,         // Virtual Address of the Entry Point
+    pub ph_offset: u32,     // File Offset of the Program Header Table
+    pub sh_offset: u32,     // File Offset of the Section Header Table
+    pub flags: u32,         // Architecture Dependent Flags
+    pub header_size: u16,   // Size of this Header
+    pub ph_entry_size: u16, // Size of a Program Header
+    pub ph_count: u16,      // Number of Program Headers
+    pub sh_entry_size: u16, // Size of a Section Header
+    pub sh_count: u16,      // Number of Section Headers
+    pub sh_str_index: u16,  // Section Holding the Section Names
+}
+
+/// ELF32 program header, describing a segment to be loaded in memory
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramHeader {
+    pub kind: u32,          // Segment Type (PT_LOAD, PT_DYNAMIC, etc)
+    pub offset: u32,        // File Offset of the Segment
+    pub virtual_addr: u32,  // Virtual Address of the Segment
+    pub physical_addr: u32, // Unused
+    pub file_size: u32,     // Bytes of the Segment present in the File
+    pub memory_size: u32,   // Bytes of the Segment in Memory (the rest is BSS)
+    pub flags: u32,         // Segment Permissions (see ProgramFlags)
+    pub align: u32,
+}
+
+bitflags! {
+    /// Permissions of a loaded segment
+    pub struct ProgramFlags: u32 {
+        const EXECUTE = 1 << 0;
+        const WRITE = 1 << 1;
+        const READ = 1 << 2;
+    }
+}
+
+/// Executable loaded into an address space
+#[derive(Debug, Clone, Copy)]
+pub struct ElfImage {
+    pub entry: usize, // Virtual Address of the Entry Point
+    pub end: usize,   // Highest Virtual Address used by the Segments (page aligned)
+}
@@ -0,0 +1,183 @@
+/// Loader of ELF32 executables into user address spaces. Only statically linked i386
+/// executables are supported: every PT_LOAD segment is mapped at its virtual address, the
+/// bytes present in the file are copied over and the remaining ones (BSS) are zero-filled.
+/// See more at https://wiki.osdev.org/ELF
+use abi::errno::Errno;
+use core::mem::size_of;
+
+use super::defs::*;
+use crate::{
+    memory::{
+        defs::{PageDirectory, PageFlags, PAGE_SIZE, USER_STACK_PAGES, USER_STACK_TOP},
+        vm::{allocate_user_pages, copy_to_page_dir, fill_page_dir},
+    },
+    ROUND_DOWN, ROUND_UP,
+};
+
+/// Segments must end before the user stack
+const USER_SPACE_END: usize = USER_STACK_TOP - USER_STACK_PAGES * PAGE_SIZE;
+
+impl ElfHeader {
+    /// Read the header at the start of the image. The image may not be aligned in memory.
+    ///
+    /// # Safety
+    /// image must point to at least size readable bytes.
+    pub unsafe fn read(image: *const u8, size: usize) -> Result<Self, &'static str> {
+        if size < size_of::<ElfHeader>() {
+            return Err("[ERR] ELF image is too small");
+        }
+
+        let header = (image as *const ElfHeader).read_unaligned();
+        header.validate(size)?;
+
+        Ok(header)
+    }
+
+    /// Ensure the executable can be loaded by this Kernel
+    fn validate(&self, size: usize) -> Result<(), &'static str> {
+        if self.ident[0..4] != ELF_MAGIC {
+            return Err("[ERR] Not an ELF image");
+        }
+
+        if self.ident[4] != ELF_CLASS_32
+            || self.ident[5] != ELF_DATA_LSB
+            || self.ident[6] != ELF_VERSION_CURRENT
+        {
+            return Err("[ERR] ELF image is not 32-bit little endian");
+        }
+
+        if self.kind != ELF_TYPE_EXECUTABLE || self.machine != ELF_MACHINE_386 {
+            return Err("[ERR] ELF image is not an i386 executable");
+        }
+
+        if self.ph_entry_size as usize != size_of::<ProgramHeader>() {
+            return Err("[ERR] ELF program header size mismatch");
+        }
+
+        let ph_end = (self.ph_offset as usize)
+            .checked_add(self.ph_count as usize * size_of::<ProgramHeader>())
+            .ok_or("[ERR] ELF program headers out of bounds")?;
+
+        if ph_end > size {
+            return Err("[ERR] ELF program headers out of bounds");
+        }
+
+        Ok(())
+    }
+
+    /// Program header at the given index. Bounds are checked by validate.
+    unsafe fn program_header(&self, image: *const u8, index: usize) -> ProgramHeader {
+        let offset = self.ph_offset as usize + index * size_of::<ProgramHeader>();
+        (image.add(offset) as *const ProgramHeader).read_unaligned()
+    }
+}
+
+impl ProgramHeader {
+    /// Ensure the segment lies inside the image and inside the user space
+    fn validate(&self, size: usize) -> Result<(), &'static str> {
+        let file_end = (self.offset as usize).checked_add(self.file_size as usize);
+        let memory_end = (self.virtual_addr as usize).checked_add(self.memory_size as usize);
+
+        if self.file_size > self.memory_size {
+            return Err("[ERR] ELF segment is bigger in file than in memory");
+        }
+
+        match file_end {
+            Some(end) if end <= size => {}
+            _ => return Err("[ERR] ELF segment out of bounds"),
+        }
+
+        match memory_end {
+            Some(end) if end <= USER_SPACE_END => Ok(()),
+            _ => Err("[ERR] ELF segment overlaps the user stack or Kernel memory"),
+        }
+    }
+
+    /// Page permissions of the segment. x86 (without PAE) has no way of preventing execution,
+    /// so only the write permission is honored.
+    pub fn page_flags(&self) -> PageFlags {
+        let flags = ProgramFlags::from_bits_truncate(self.flags);
+
+        match flags.contains(ProgramFlags::WRITE) {
+            true => PageFlags::USER | PageFlags::WRITABLE,
+            false => PageFlags::USER,
+        }
+    }
+}
+
+/// Map a segment into the page directory. Segments may share a page (e.g. the end of the text
+/// and the start of the data), in which case the shared page gets the permissions of both.
+fn map_segment(page_dir: PageDirectory, segment: &ProgramHeader) -> Result<(), &'static str> {
+    let start = ROUND_DOWN!(segment.virtual_addr as usize, PAGE_SIZE);
+    let end = ROUND_UP!(
+        (segment.virtual_addr + segment.memory_size) as usize,
+        PAGE_SIZE
+    );
+    let flags = segment.page_flags();
+
+    for address in (start..end).step_by(PAGE_SIZE) {
+        match page_dir.mapping(address) {
+            Some(entry) => entry.set_flags(entry.flags() | flags),
+            None => allocate_user_pages(page_dir, address, address + PAGE_SIZE, flags)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Load an ELF executable into the (user half of the) provided page directory, returning its
+/// entry point and the end of its highest segment. Fails with ENOEXEC if the image is not a
+/// valid executable and with ENOMEM if it cannot be mapped. On failure, the pages mapped so
+/// far are left in the page directory, so it should be released by the caller.
+///
+/// # Safety
+/// image must point to at least size readable bytes, which must not overlap the frames mapped
+/// into page_dir. page_dir must be a valid page directory that no running process uses yet,
+/// as its user half is modified while loading.
+pub unsafe fn load_elf(
+    page_dir: PageDirectory,
+    image: *const u8,
+    size: usize,
+) -> Result<ElfImage, Errno> {
+    let header = ElfHeader::read(image, size).map_err(|_| Errno::ENOEXEC)?;
+    let mut end = 0;
+
+    for index in 0..header.ph_count as usize {
+        let segment = header.program_header(image, index);
+
+        if segment.kind != PROGRAM_TYPE_LOAD || segment.memory_size == 0 {
+            continue;
+        }
+
+        segment.validate(size).map_err(|_| Errno::ENOEXEC)?;
+        map_segment(page_dir, &segment).map_err(|_| Errno::ENOMEM)?;
+
+        let address = segment.virtual_addr as usize;
+        let file_size = segment.file_size as usize;
+        let bss_size = (segment.memory_size - segment.file_size) as usize;
+
+        copy_to_page_dir(
+            page_dir,
+            address,
+            image.add(segment.offset as usize),
+            file_size,
+        )
+        .map_err(|_| Errno::ENOMEM)?;
+        fill_page_dir(page_dir, address + file_size, 0, bss_size).map_err(|_| Errno::ENOMEM)?;
+
+        end = end.max(ROUND_UP!(address + segment.memory_size as usize, PAGE_SIZE));
+    }
+
+    if end == 0 {
+        return Err(Errno::ENOEXEC); // No loadable segments
+    }
+
+    if page_dir.mapping(header.entry as usize).is_none() {
+        return Err(Errno::ENOEXEC); // Entry point is not mapped
+    }
+
+    Ok(ElfImage {
+        entry: header.entry as usize,
+        end,
+    })
+}
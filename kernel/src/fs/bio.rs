@@ -1,75 +1,125 @@
-use super::defs::*; 
-use spin::Mutex;
-use alloc::collections::VecDeque;
+/// Buffer cache, xv6 style. A fixed number of buffers hold copies of disk blocks, so recently
+/// used blocks are not read again. Each buffer has its own sleep lock, held by its user across
+/// disk I/O, so accesses to different blocks proceed in parallel. Unused buffers are recycled,
+/// least recently used first.
+use super::defs::*;
+use crate::threading::defs::{SleepLock, SpinLock};
+use alloc::boxed::Box;
+use core::{
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+};
 use lazy_static::lazy_static;
 use crate::{println, devices::{defs::{B_VALID, B_DIRTY}, ide::GLOBAL_IDE}};
-use hashbrown::HashMap;
 
 impl BufCache {
     pub fn new(capacity: usize) -> Self {
-        Self {
-            cache: HashMap::new(),
-            keys: VecDeque::with_capacity(capacity),
-            capacity,
-        }
+        let slots = (0..capacity)
+            .map(|_| CachedBuf {
+                key: None,
+                refs: 0,
+                buf: Box::leak(Box::new(SleepLock::new(Buf::new(0, 0)))),
+            })
+            .collect();
+
+        Self { slots }
     }
 
-    // Gets a buffer from the buffer cache
-    pub fn get(&mut self, key: &(u32, usize)) -> Option<&mut Buf> {
-        if let Some(value) = self.cache.get_mut(key) {
-            self.keys.retain(|val| val != key);
-            self.keys.push_front(*key);
-            Some(value)
-        } else {
-            None
+    // Finds the buffer holding the block, or recycles the least recently used unused buffer
+    // for it. The buffer is referenced, but not locked.
+    fn get(&mut self, dev: u32, blockno: usize) -> &'static SleepLock<Buf> {
+        let key = Some((dev, blockno));
+
+        if let Some(slot) = self.slots.iter_mut().find(|slot| slot.key == key) {
+            slot.refs += 1;
+            return slot.buf;
         }
+
+        let slot = self
+            .slots
+            .iter_mut()
+            .rev()
+            .find(|slot| slot.refs == 0)
+            .expect("[FATAL] No Free Buffers");
+
+        // Nobody references the buffer, so it is not locked either. Its first user notices the
+        // block changed, and drops the old contents (see buf_get).
+        slot.key = key;
+        slot.refs = 1;
+        slot.buf
     }
 
-    // Puts a buffer into the buffer cache
-    pub fn put(&mut self, key: (u32, usize), value: Buf) {
-        if self.keys.len() == self.capacity {
-            if let Some(oldest_key) = self.keys.pop_back() {
-                self.cache.remove(&oldest_key);
-            }
-        }
-        self.keys.push_front(key);
-        self.cache.insert(key, value);
+    // Drops a reference to the buffer, which becomes the most recently used one
+    fn put(&mut self, buf: &'static SleepLock<Buf>) {
+        let index = self
+            .slots
+            .iter()
+            .position(|slot| core::ptr::eq(slot.buf, buf))
+            .expect("[FATAL] Buffer Not Cached");
+
+        let mut slot = self.slots.remove(index).unwrap();
+        slot.refs -= 1;
+        self.slots.push_front(slot);
     }
+}
 
-    // Gets a buffer from the cache and returns it. If the buffer does not 
-    // exist in the cache, it will create a new one. This may result in a buffer 
-    // release if the cache is already full.
-    pub fn buf_get(&mut self, dev: u32, blockno: usize) -> &mut Buf {
-        if self.get(&(dev, blockno)).is_some() {
-            self.get(&(dev, blockno)).unwrap()
-        } else {
-            let new_buf = Buf::new(dev, blockno);
-            self.put((dev, blockno), new_buf);
-            self.get(&(dev, blockno)).unwrap()
-        }
+lazy_static! {
+    pub static ref BUF_CACHE: SpinLock<BufCache> = SpinLock::new(BufCache::new(MAX_BUFS));
+}
+
+// Returns the locked buffer of the block. Its contents are only valid if B_VALID is set.
+pub fn buf_get(dev: u32, blockno: usize) -> BufGuard {
+    let lock = BUF_CACHE.lock().get(dev, blockno);
+    let mut guard = lock.lock();
+
+    // The buffer was recycled for this block
+    if guard.dev != dev || guard.blockno != blockno {
+        *guard = Buf::new(dev, blockno);
     }
-    
-    // Returns a buffer that contains the data from the disk at the specified dev / block
-    pub fn buf_read(&mut self, dev: u32, blockno: usize) -> &Buf {
-        let buffer = self.buf_get(dev, blockno);
 
-        if buffer.flags & B_VALID == 0 {
-            GLOBAL_IDE.lock().iderw(buffer);
-        }
+    BufGuard {
+        lock,
+        guard: ManuallyDrop::new(guard),
+    }
+}
+
+// Returns a locked buffer that contains the data from the disk at the specified dev / block
+pub fn buf_read(dev: u32, blockno: usize) -> BufGuard {
+    let mut buffer = buf_get(dev, blockno);
+
+    if buffer.flags & B_VALID == 0 {
+        GLOBAL_IDE.iderw(&mut buffer);
+    }
+
+    buffer
+}
 
-        buffer
+// Writes a buffer's data to disk
+pub fn buf_write(buffer: &mut BufGuard) {
+    buffer.flags |= B_DIRTY;
+    GLOBAL_IDE.iderw(buffer);
+}
+
+impl Deref for BufGuard {
+    type Target = Buf;
+
+    fn deref(&self) -> &Buf {
+        &self.guard
     }
-    
-    // Writes a buffer's data to disk
-    pub fn buf_write(&mut self, buffer: &mut Buf) {
-        buffer.flags |= B_DIRTY;
-        GLOBAL_IDE.lock().iderw(buffer);
+}
+
+impl DerefMut for BufGuard {
+    fn deref_mut(&mut self) -> &mut Buf {
+        &mut self.guard
     }
-    
 }
 
-lazy_static! {
-    pub static ref BUF_CACHE: Mutex<BufCache> = Mutex::new(BufCache::new(MAX_BUFS));
+impl Drop for BufGuard {
+    // Releases the buffer: unlocks it first, so it is never recycled while locked
+    fn drop(&mut self) {
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        BUF_CACHE.lock().put(self.lock);
+    }
 }
 
 // Currenly useless, might remove 
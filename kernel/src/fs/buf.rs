@@ -8,7 +8,6 @@ impl Buf {
             dev,
             blockno,
             data: [0; B_SIZE],
-            qnext: None,
         }
     }
 }
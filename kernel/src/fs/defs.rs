@@ -1,9 +1,7 @@
-use alloc::{
-    collections::VecDeque,
-    boxed::Box,
-};
+use alloc::collections::VecDeque;
+use core::mem::ManuallyDrop;
 use crate::devices::defs::SECTOR_SIZE;
-use hashbrown::HashMap;
+use crate::threading::defs::{SleepLock, SleepLockGuard};
 
 pub const MAX_BUFS: usize = 10; // Maximum buffers in buffer cache
 
@@ -13,11 +11,29 @@ pub struct Buf {
     pub blockno: usize, // Block number of disk
     pub flags: u8, // Buffer flags (Dirty, Valid, etc.)
     pub data: [u8; SECTOR_SIZE], // Data in buffer
-    pub qnext: Option<Box<Buf>>, // Pointer to the next buffer in idequeue
+}
+
+/// Slot of the buffer cache. The buffer itself has its own sleep lock, as it is held across disk
+/// I/O, while the rest is guarded by the cache lock.
+pub struct CachedBuf {
+    pub key: Option<(u32, usize)>, // Device and block number held, if any
+    pub refs: usize, // Users of the buffer, which can not be recycled meanwhile
+    pub buf: &'static SleepLock<Buf>, // Never freed, so the disk driver can point to it
 }
 
 pub struct BufCache {
-    pub cache: HashMap<(u32, usize), Buf>, // Maps buffer dev, blockno to a buffer
-    pub keys: VecDeque<(u32, usize)>, // Sorts recency of buffer use
-    pub capacity: usize, // Current amount of buffers in the cache
+    pub slots: VecDeque<CachedBuf>, // Most recently used first
+}
+
+/// A locked buffer of the cache. The buffer is released once dropped.
+pub struct BufGuard {
+    pub(super) lock: &'static SleepLock<Buf>,
+    pub(super) guard: ManuallyDrop<SleepLockGuard<'static, Buf>>,
+}
+
+/// File linked into the Kernel image (see embedded.rs)
+pub struct EmbeddedFile {
+    pub path: &'static str,
+    pub start: *const u8,
+    pub size: usize,
 }
@@ -0,0 +1,51 @@
+/// Executables linked into the Kernel image (see build_kernel in Makefile.toml): the assembly
+/// init program, and the programs of the user crate (user/src/bin) under /bin. This is not a
+/// file system: until the Kernel has an on-disk one, these are the only files programs can be
+/// started from, and paths are resolved against this table.
+use super::defs::EmbeddedFile;
+
+extern "C" {
+    static _binary_init_start: u8;
+    static _binary_init_size: u8;
+    static _binary_user_init_start: u8;
+    static _binary_user_init_size: u8;
+    static _binary_user_test_start: u8;
+    static _binary_user_test_size: u8;
+}
+
+/// Every file linked into the Kernel image, along with its path
+fn embedded_files() -> [EmbeddedFile; 3] {
+    unsafe {
+        [
+            EmbeddedFile {
+                path: "/init",
+                start: &_binary_init_start as *const u8,
+                size: &_binary_init_size as *const u8 as usize,
+            },
+            EmbeddedFile {
+                path: "/bin/init",
+                start: &_binary_user_init_start as *const u8,
+                size: &_binary_user_init_size as *const u8 as usize,
+            },
+            EmbeddedFile {
+                path: "/bin/test",
+                start: &_binary_user_test_start as *const u8,
+                size: &_binary_user_test_size as *const u8 as usize,
+            },
+        ]
+    }
+}
+
+impl EmbeddedFile {
+    pub fn data(&self) -> &'static [u8] {
+        unsafe { core::slice::from_raw_parts(self.start, self.size) }
+    }
+}
+
+/// Contents of the file at the given (absolute) path
+pub fn lookup(path: &str) -> Option<&'static [u8]> {
+    embedded_files()
+        .iter()
+        .find(|file| file.path == path)
+        .map(|file| file.data())
+}
@@ -1,3 +1,4 @@
 pub mod buf;
 pub mod defs;
 pub mod bio;
+pub mod embedded;
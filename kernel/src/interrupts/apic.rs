@@ -2,15 +2,45 @@
 /// delivery of interrupt requests to processors. Multiple components external to the processor
 /// (keyboard, timer, peripherals, etc) may try to generate an IRQ, and it is the APIC's job to
 /// deliver those. You can find more information on PIC here: https://wiki.osdev.org/8259_PIC
+/// Machines described by MP tables have a Local APIC per processor and an I/O APIC, which are
+/// used instead of the legacy 8259 PIC. The 8259 is only kept when no APIC is found.
+
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use pic8259_x86::ChainedPics;
-use spin;
+
+use super::{
+    defs::{
+        irq::MASKED_PIC_OFFSET,
+        mp::{IMCR_ADDRESS, IMCR_APIC_MODE, IMCR_DATA, IMCR_SELECT},
+    },
+    ioapic, lapic,
+    mp::{setup_mp, MP_CONFIGURATION},
+};
+use crate::{
+    println,
+    threading::defs::SpinLock,
+    x86::helpers::{inb, outb},
+};
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
-pub static PICS: spin::Mutex<ChainedPics> =
-    spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+/// Command ports of the master and slave PICs
+pub const PIC_1_COMMAND: u16 = 0x20;
+pub const PIC_2_COMMAND: u16 = 0xA0;
+
+/// OCW3 command selecting the In-Service Register for the next read of a command port
+pub const PIC_READ_ISR: u8 = 0x0B;
+
+/// End-of-interrupt command
+pub const PIC_EOI: u8 = 0x20;
+
+pub static PICS: SpinLock<ChainedPics> =
+    SpinLock::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+
+/// Are interrupts delivered by the APICs (instead of the 8259 PIC)?
+static APIC_ENABLED: AtomicBool = AtomicBool::new(false);
 
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
@@ -42,3 +72,84 @@ impl InterruptIndex {
         usize::from(self.as_u8())
     }
 }
+
+/// In-Service Register of both PICs (slave in the high byte). A bit is set for every IRQ the
+/// PICs delivered and are still waiting an end-of-interrupt for.
+pub fn read_isr() -> u16 {
+    outb(PIC_1_COMMAND, PIC_READ_ISR);
+    outb(PIC_2_COMMAND, PIC_READ_ISR);
+
+    ((inb(PIC_2_COMMAND) as u16) << 8) | inb(PIC_1_COMMAND) as u16
+}
+
+/// Acknowledge an interrupt on the master PIC only
+pub fn notify_master_end_of_interrupt() {
+    outb(PIC_1_COMMAND, PIC_EOI);
+}
+
+pub fn apic_enabled() -> bool {
+    APIC_ENABLED.load(Ordering::SeqCst)
+}
+
+/// Acknowledge the interrupt being serviced on whichever controller delivered it
+pub fn notify_end_of_interrupt(vector: u8) {
+    if apic_enabled() {
+        lapic::notify_end_of_interrupt();
+    } else {
+        unsafe { PICS.lock().notify_end_of_interrupt(vector) };
+    }
+}
+
+/// Let an IRQ reach the boot processor. The 8259 delivers every IRQ, while the I/O APIC starts
+/// with all inputs masked. With APICs, the timer tick comes from the Local APIC instead of IRQ 0.
+pub fn enable_irq(irq: InterruptIndex) {
+    if !apic_enabled() || irq.as_u8() == InterruptIndex::Timer.as_u8() {
+        return;
+    }
+
+    let config = MP_CONFIGURATION.lock().expect("[FATAL] APIC Without MP Tables");
+    let pin = config.irq_pins[(irq.as_u8() - PIC_1_OFFSET) as usize];
+
+    ioapic::enable_pin(pin, irq.as_u8(), config.bsp_apic_id);
+}
+
+pub fn disable_irq(irq: InterruptIndex) {
+    if !apic_enabled() || irq.as_u8() == InterruptIndex::Timer.as_u8() {
+        return;
+    }
+
+    let config = MP_CONFIGURATION.lock().expect("[FATAL] APIC Without MP Tables");
+    ioapic::disable_pin(config.irq_pins[(irq.as_u8() - PIC_1_OFFSET) as usize]);
+}
+
+/// Remap the 8259 away from the exception vectors, then switch to the APICs if the MP tables
+/// announce them. The 8259 is masked in that case, but may still raise spurious interrupts, so
+/// it is moved to vectors of its own (see irq.rs).
+pub fn setup_interrupt_controller() {
+    unsafe { PICS.lock().initialize() };
+
+    if !setup_mp() {
+        println!("[KERNEL] No APIC Found, Using 8259 PIC");
+        return;
+    }
+
+    let config = MP_CONFIGURATION.lock().expect("[FATAL] APIC Without MP Tables");
+
+    unsafe {
+        let mut pics = PICS.lock();
+        *pics = ChainedPics::new(MASKED_PIC_OFFSET, MASKED_PIC_OFFSET + 8);
+        pics.initialize();
+        pics.disable();
+    }
+
+    // Older machines wire the 8259 straight to the processor, unless told otherwise
+    if config.imcr_present {
+        outb(IMCR_ADDRESS, IMCR_SELECT);
+        outb(IMCR_DATA, inb(IMCR_DATA) | IMCR_APIC_MODE);
+    }
+
+    lapic::setup_lapic(config.lapic_address);
+    ioapic::setup_ioapic(config.ioapic_id, config.ioapic_address);
+
+    APIC_ENABLED.store(true, Ordering::SeqCst);
+}
@@ -1,15 +1,268 @@
 use bitflags::bitflags;
 use core::marker::PhantomData;
 
+use crate::scheduler::defs::process::TrapFrame;
+
 /// System Call Constants (system_call.rs)
 
 pub mod system_call {
-    pub const NUM_SYS_CALLS: usize = 1;
+    /// System Call numbers are shared with the user space
+    pub use abi::syscall::*;
+}
+
+/// Trap Constants (interrupt_handlers.rs)
+
+pub mod trap {
+    /// Number of vectors in the IDT, each with its own entry stub (trap.asm)
+    pub const IDT_ENTRIES: usize = 256;
+
+    /// Vectors below this one are reserved for processor exceptions
+    pub const NUM_EXCEPTIONS: usize = 32;
+
+    pub const NON_MASKABLE_INTERRUPT: usize = 2;
+    pub const BREAKPOINT: usize = 3;
+    pub const DOUBLE_FAULT: usize = 8;
+    pub const PAGE_FAULT: usize = 14;
+
+    /// Names of the processor exceptions, indexed by vector
+    pub const EXCEPTION_NAMES: [&str; NUM_EXCEPTIONS] = [
+        "DIVISION BY ZERO",
+        "DEBUG",
+        "NON MASKABLE INTERRUPT",
+        "BREAKPOINT",
+        "OVERFLOW",
+        "BOUND RANGE EXCEEDED",
+        "INVALID OPCODE",
+        "DEVICE NOT AVAILABLE",
+        "DOUBLE FAULT",
+        "COPROCESSOR SEGMENT OVERRUN",
+        "INVALID TSS",
+        "SEGMENT NOT PRESENT",
+        "STACK SEGMENT FAULT",
+        "GENERAL PROTECTION FAULT",
+        "PAGE FAULT",
+        "RESERVED",
+        "X87 FLOATING POINT",
+        "ALIGNMENT CHECK",
+        "MACHINE CHECK",
+        "SIMD FLOATING POINT",
+        "VIRTUALIZATION",
+        "CONTROL PROTECTION",
+        "RESERVED",
+        "RESERVED",
+        "RESERVED",
+        "RESERVED",
+        "RESERVED",
+        "RESERVED",
+        "HYPERVISOR INJECTION",
+        "VMM COMMUNICATION",
+        "SECURITY",
+        "RESERVED",
+    ];
+}
+
+/// IRQ Constants (irq.rs)
+
+pub mod irq {
+    /// IRQ lines of the chained PICs
+    pub const NUM_IRQS: usize = 16;
+
+    /// Maximum number of handlers sharing a single IRQ line
+    pub const MAX_IRQ_HANDLERS: usize = 4;
+
+    /// Vectors of the 8259 once masked in favour of the APICs. Anything it still delivers there
+    /// is spurious, and kept apart from the I/O APIC vectors of the same IRQs.
+    pub const MASKED_PIC_OFFSET: u8 = 0xE0;
+
+    /// Lowest priority line of each PIC, where spurious interrupts are delivered
+    pub const SPURIOUS_MASTER_IRQ: usize = 7;
+    pub const SPURIOUS_SLAVE_IRQ: usize = 15;
+}
+
+/// MultiProcessor Table Constants (mp.rs)
+
+pub mod mp {
+    /// Maximum number of processors tracked from the MP tables
+    pub const MAX_CPUS: usize = 8;
+
+    /// BIOS Data Area words holding the EBDA segment and the size (in KB) of base memory
+    pub const BDA_EBDA_SEGMENT: usize = 0x40E;
+    pub const BDA_BASE_MEMORY_SIZE: usize = 0x413;
+
+    /// Read-only BIOS area, last place searched for the floating pointer
+    pub const BIOS_ROM_START: usize = 0xF0000;
+    pub const BIOS_ROM_END: usize = 0x100000;
+
+    pub const MP_FLOATING_SIGNATURE: &[u8; 4] = b"_MP_";
+    pub const MP_CONFIG_SIGNATURE: &[u8; 4] = b"PCMP";
+    pub const MP_IMCR_PRESENT: u8 = 1 << 7;
+
+    /// Configuration table entry types
+    pub const MP_PROCESSOR: u8 = 0;
+    pub const MP_BUS: u8 = 1;
+    pub const MP_IOAPIC: u8 = 2;
+    pub const MP_IO_INTERRUPT: u8 = 3;
+    pub const MP_LOCAL_INTERRUPT: u8 = 4;
+
+    pub const MP_PROCESSOR_ENABLED: u8 = 1 << 0;
+    pub const MP_PROCESSOR_BSP: u8 = 1 << 1;
+    pub const MP_BUS_ISA: &[u8; 6] = b"ISA   ";
+    pub const MP_INTERRUPT_VECTORED: u8 = 0;
+
+    /// Interrupt Mode Configuration Register, routes the 8259 either to the BSP or the APICs
+    pub const IMCR_ADDRESS: u16 = 0x22;
+    pub const IMCR_DATA: u16 = 0x23;
+    pub const IMCR_SELECT: u8 = 0x70;
+    pub const IMCR_APIC_MODE: u8 = 0x01;
+}
+
+/// Local APIC Constants (lapic.rs)
+
+pub mod lapic {
+    /// Register offsets (in bytes) from the Local APIC base address
+    pub const LAPIC_ID: usize = 0x20;
+    pub const LAPIC_VERSION: usize = 0x30;
+    pub const LAPIC_TASK_PRIORITY: usize = 0x80;
+    pub const LAPIC_EOI: usize = 0xB0;
+    pub const LAPIC_SPURIOUS: usize = 0xF0;
+    pub const LAPIC_ERROR_STATUS: usize = 0x280;
+    pub const LAPIC_ICR_LOW: usize = 0x300; // Interrupt Command Register, writing it sends the IPI
+    pub const LAPIC_ICR_HIGH: usize = 0x310;
+    pub const LAPIC_TIMER: usize = 0x320;
+    pub const LAPIC_PERFORMANCE_COUNTER: usize = 0x340;
+    pub const LAPIC_LINT0: usize = 0x350;
+    pub const LAPIC_LINT1: usize = 0x360;
+    pub const LAPIC_ERROR: usize = 0x370;
+    pub const LAPIC_TIMER_INITIAL_COUNT: usize = 0x380;
+    pub const LAPIC_TIMER_CURRENT_COUNT: usize = 0x390;
+    pub const LAPIC_TIMER_DIVIDE: usize = 0x3E0;
+
+    pub const LAPIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+    pub const LAPIC_MASKED: u32 = 1 << 16;
+    pub const LAPIC_TIMER_PERIODIC: u32 = 1 << 17;
+    pub const LAPIC_TIMER_DIVIDE_BY_16: u32 = 0x3;
+
+    /// Inter-Processor Interrupt (IPI) command fields
+    pub const ICR_INIT: u32 = 0b101 << 8;
+    pub const ICR_STARTUP: u32 = 0b110 << 8;
+    pub const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+    pub const ICR_ASSERT: u32 = 1 << 14;
+    pub const ICR_LEVEL_TRIGGERED: u32 = 1 << 15;
+    pub const ICR_DESTINATION_SHIFT: u32 = 24;
+
+    /// Vector of the Local APIC spurious interrupts. Those must not be acknowledged.
+    pub const LAPIC_SPURIOUS_VECTOR: usize = 0xFF;
 
-    /// System Call Numbers
-    pub const PRINT_TRAPFRAME_SYSCALL: usize = 0;
+    /// Length of the LAPIC timer calibration against the PIT
+    pub const LAPIC_CALIBRATION_MS: usize = 10;
 }
 
+/// I/O APIC Constants (ioapic.rs)
+
+pub mod ioapic {
+    /// Memory mapped registers: a register is selected, then read or written through the window
+    pub const IOAPIC_REGISTER_SELECT: usize = 0x00;
+    pub const IOAPIC_WINDOW: usize = 0x10;
+
+    pub const IOAPIC_ID: u32 = 0x00;
+    pub const IOAPIC_VERSION: u32 = 0x01;
+    pub const IOAPIC_REDIRECTION_TABLE: u32 = 0x10; // Two registers per entry (low, high)
+
+    pub const IOAPIC_MASKED: u32 = 1 << 16;
+    pub const IOAPIC_DESTINATION_SHIFT: u32 = 24;
+}
+
+/// Floating pointer structure announcing the MP configuration table
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct MpFloatingPointer {
+    pub signature: [u8; 4],   // "_MP_"
+    pub config_table: u32,    // Physical address of the configuration table
+    pub length: u8,           // Size in 16 byte paragraphs (always 1)
+    pub spec_revision: u8,    // MP specification version (1.1 or 1.4)
+    pub checksum: u8,         // All bytes must add up to 0
+    pub default_config: u8,   // Default configuration used, if not 0
+    pub features: u8,         // Bit 7 set if the IMCR is present
+    pub reserved: [u8; 3],
+}
+
+/// Header of the MP configuration table. Entries of varying size follow it.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct MpConfigHeader {
+    pub signature: [u8; 4],   // "PCMP"
+    pub length: u16,          // Size of the header plus the base entries
+    pub version: u8,          // 1 or 4
+    pub checksum: u8,         // All bytes must add up to 0
+    pub product: [u8; 20],    // OEM and product identification
+    pub oem_table: u32,
+    pub oem_length: u16,
+    pub entry_count: u16,     // Number of entries following the header
+    pub lapic_address: u32,   // Physical address of the Local APICs
+    pub extended_length: u16,
+    pub extended_checksum: u8,
+    pub reserved: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct MpProcessor {
+    pub kind: u8,          // MP_PROCESSOR
+    pub apic_id: u8,       // Local APIC ID
+    pub apic_version: u8,
+    pub flags: u8,         // Enabled, Bootstrap Processor
+    pub signature: [u8; 4],
+    pub features: u32,
+    pub reserved: [u8; 8],
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct MpBus {
+    pub kind: u8, // MP_BUS
+    pub id: u8,
+    pub name: [u8; 6], // "ISA   ", "PCI   ", etc
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct MpIoApic {
+    pub kind: u8, // MP_IOAPIC
+    pub id: u8,
+    pub version: u8,
+    pub flags: u8,
+    pub address: u32, // Physical address of the I/O APIC
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct MpInterrupt {
+    pub kind: u8,           // MP_IO_INTERRUPT or MP_LOCAL_INTERRUPT
+    pub interrupt_type: u8, // Vectored, NMI, SMI, ExtINT
+    pub flags: u16,         // Polarity and trigger mode
+    pub source_bus: u8,
+    pub source_irq: u8,
+    pub destination_apic: u8,
+    pub destination_pin: u8, // I/O APIC input (INTIN) the IRQ is wired to
+}
+
+/// What the Kernel needs from the MP tables: the processors, and where interrupts are routed
+#[derive(Debug, Clone, Copy)]
+pub struct MpConfiguration {
+    pub lapic_address: usize,
+    pub ioapic_id: u8,
+    pub ioapic_address: usize,
+    pub processors: [u8; mp::MAX_CPUS], // Local APIC IDs of the enabled processors
+    pub num_processors: usize,
+    pub bsp_apic_id: u8,
+    pub irq_pins: [u8; irq::NUM_IRQS], // I/O APIC input of every ISA IRQ
+    pub imcr_present: bool,
+}
+
+/// Device handler of an IRQ line. Runs after the interrupt was acknowledged, with interrupts
+/// disabled, and receives the trap frame of the interrupted code.
+pub type IrqHandler = fn(&mut TrapFrame);
+
 /// Structure of a pointer to a IDT. Must be passed in this format
 /// to a lidt call.
 #[derive(Debug, Clone, Copy)]
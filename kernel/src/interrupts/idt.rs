@@ -2,9 +2,14 @@ use core::{marker::PhantomData, mem::size_of};
 
 use lazy_static::lazy_static;
 
-use crate::{interrupts::interrupt_handlers::*, println, x86::helpers::lidt};
+use crate::{println, x86::helpers::lidt};
 
-use super::defs::*;
+use super::defs::{system_call::SYSCALL_VECTOR, trap::IDT_ENTRIES, *};
+
+extern "C" {
+    /// Addresses of the entry stubs of every vector (trap.asm)
+    static trap_vectors: [u32; IDT_ENTRIES];
+}
 
 impl<F> Gate<F> {
     // Implementation of an empty gate. Used to initialized gates
@@ -132,6 +137,12 @@ impl IDT {
         }
     }
 
+    /// The table is laid out as IDT_ENTRIES consecutive gates, so it can also be accessed by
+    /// vector number.
+    fn gates(&mut self) -> &mut [Gate<InterruptHandler>; IDT_ENTRIES] {
+        unsafe { &mut *(self as *mut IDT as *mut [Gate<InterruptHandler>; IDT_ENTRIES]) }
+    }
+
     /// Creates the descriptor pointer for this table. This pointer can only be
     /// safely used if the table is never modified or destroyed while in use.
     fn pointer(&self) -> InterruptDescriptorTablePointer {
@@ -153,27 +164,26 @@ impl IDT {
 lazy_static! {
     static ref GLOBAL_IDT: IDT = {
         let mut global_idt = IDT::new();
+        let gates = global_idt.gates();
+
+        // User System Calls at Gate 64 are traps, and may be issued from user mode
+        gates[SYSCALL_VECTOR as usize].set_flags(GateFlags::TRAPGATE as u8 | GateFlags::DPL3 as u8);
+
+        // Every vector goes through its entry stub, which then calls the trap dispatcher
+        for (vector, gate) in gates.iter_mut().enumerate() {
+            unsafe { gate.set_handler_addr(trap_vectors[vector]) };
+        }
 
-        // Setup User System Call Interrupt Handler at Gate 64
-        global_idt.gp_interrupts[15].set_flags(GateFlags::TRAPGATE as u8 | GateFlags::DPL3 as u8);
-        global_idt.gp_interrupts[15].set_handler_fn(user_interrupt_switch);
-
-        // Setup Handlers
-        global_idt.div_by_zero.set_handler_fn(div_by_zero_handler);
-        global_idt.breakpoint.set_handler_fn(breakpoint_handler);
-        global_idt.gen_protection_fault.set_handler_fn(gen_protection_fault);
-        global_idt.double_fault.set_handler_fn(double_fault_handler);
-        global_idt.page_fault.set_handler_fn(page_fault);
-        global_idt.overflow.set_handler_fn(overflow);
-        global_idt.bound_range_exceeded.set_handler_fn(bound_range);
-        global_idt.primary_ata_hard_disk.set_handler_fn(primary_disk_access);
-        global_idt.secondary_ata_hard_disk.set_handler_fn(secondary_disk_access);
-        global_idt.timer.set_handler_fn(timer_interrupt);
         global_idt
     };
 }
 
-pub fn setup_idt() {
+/// Every processor shares the same IDT, but must load it on its own
+pub fn load_idt() {
     GLOBAL_IDT.load();
+}
+
+pub fn setup_idt() {
+    load_idt();
     println!("[KERNEL] Interrupt Table Initialized");
 }
@@ -1,91 +1,118 @@
-use core::arch::asm;
-
 use crate::{
+    interrupts::apic::PIC_1_OFFSET,
+    memory::{defs::PageDirectory, vm::resolve_copy_on_write},
     println,
-    scheduler::{defs::process::TrapFrame, scheduler::SCHEDULER},
-    x86::helpers::read_cr2,
-    interrupts::apic,
+    scheduler::{
+        defs::process::{TrapFrame, KILLED_EXIT_STATUS},
+        process::exit,
+        scheduler::cpu_scheduler,
+    },
+    x86::{defs::PrivilegeLevel, helpers::read_cr2},
 };
 
 use super::{
-    defs::{InterruptStackFrame, PageFaultErr},
+    defs::{
+        irq::NUM_IRQS,
+        lapic::LAPIC_SPURIOUS_VECTOR,
+        system_call::SYSCALL_VECTOR,
+        trap::{
+            BREAKPOINT, DOUBLE_FAULT, EXCEPTION_NAMES, NON_MASKABLE_INTERRUPT, NUM_EXCEPTIONS,
+            PAGE_FAULT,
+        },
+        PageFaultErr,
+    },
+    irq::{handle_irq, handle_masked_pic, handle_spurious, is_masked_pic_vector},
     system_call::handle_system_call,
 };
 
-pub extern "x86-interrupt" fn div_by_zero_handler(frame: InterruptStackFrame) {
-    println!("EXCEPTION: DIVISION BY ZERO\n{:#?}", frame);
-}
-
-pub extern "x86-interrupt" fn breakpoint_handler(frame: InterruptStackFrame) {
-    println!("EXCEPTION: BREAKPOINT\n{:#?}", frame);
-}
-
-pub extern "x86-interrupt" fn page_fault(frame: InterruptStackFrame, _error_code: PageFaultErr) {
-    panic!(
-        "[FATAL] Page Fault - eip: 0x{:X} - cr2: 0x{:X}",
-        frame.instruction_pointer,
-        read_cr2()
-    );
+/// Did the interrupt happen while running user code?
+pub fn from_user_mode(trapframe: &TrapFrame) -> bool {
+    trapframe.cs & 0b11 == PrivilegeLevel::Ring3 as u16
 }
 
-pub extern "x86-interrupt" fn primary_disk_access(frame: InterruptStackFrame) {
-    println!("EXCEPTION: DISK INTERRUPT\n{:#?}", frame);
-    unsafe {
-        apic::PICS.lock().notify_end_of_interrupt(apic::InterruptIndex::PrimaryATAHardDisk.as_u8());
+/// Exceptions caused by a user process terminate it, while exceptions caused by the Kernel are
+/// fatal
+fn handle_exception(exception: &str, trapframe: &TrapFrame) {
+    if !from_user_mode(trapframe) {
+        panic!("EXCEPTION: {}\n{:#X?}", exception, trapframe);
     }
-}
 
-pub extern "x86-interrupt" fn secondary_disk_access(frame: InterruptStackFrame) {
-    println!("EXCEPTION: DISK INTERRUPT\n{:#?}", frame);
-    unsafe {
-        apic::PICS.lock().notify_end_of_interrupt(apic::InterruptIndex::SecondaryATAHardDisk.as_u8());
-    }
-}
+    let (pid, name) = {
+        let scheduler = cpu_scheduler().lock();
+        let process = scheduler
+            .current_process
+            .as_ref()
+            .expect("[FATAL] User exception without a process");
 
-pub extern "x86-interrupt" fn timer_interrupt(_frame: InterruptStackFrame) {
-    unsafe {
-        apic::PICS.lock().notify_end_of_interrupt(apic::InterruptIndex::Timer.as_u8());
-    }
-}
+        (process.pid, process.name.clone())
+    };
 
-pub extern "x86-interrupt" fn non_maskable(frame: InterruptStackFrame) {
-    println!("EXCEPTION: NON MASKABLE INTERRUPT\n{:#?}", frame);
-}
+    println!(
+        "[KERNEL] Process {} ({}) Killed: {} - eip: 0x{:X}",
+        pid, name, exception, trapframe.eip
+    );
 
-pub extern "x86-interrupt" fn overflow(frame: InterruptStackFrame) {
-    println!("EXCEPTION: OVERFLOW\n{:#?}", frame);
+    unsafe { exit(KILLED_EXIT_STATUS) };
 }
 
-pub extern "x86-interrupt" fn bound_range(frame: InterruptStackFrame) {
-    println!("EXCEPTION: BOUND RANGE EXCEEDED\n{:#?}", frame);
-}
+fn page_fault(trapframe: &TrapFrame) {
+    let address = read_cr2();
+    let error_code = PageFaultErr::from_bits_truncate(trapframe.err as u32);
 
-pub extern "x86-interrupt" fn double_fault_handler(frame: InterruptStackFrame, _err: u32) {
-    panic!("EXCEPTION: DOUBLE FAULT\n{:#X?}", frame);
-}
+    // Writes to present, read-only pages may just be hitting a copy-on-write page
+    if error_code.contains(PageFaultErr::FAILURE_TYPE | PageFaultErr::WRITE_FAILURE)
+        && resolve_copy_on_write(PageDirectory::active(), address).is_ok()
+    {
+        return;
+    }
 
-pub extern "x86-interrupt" fn gen_protection_fault(frame: InterruptStackFrame, _err: u32) {
-    panic!("EXCEPTION: GENERAL PROTECTION FAULT\n{:#?}", frame);
+    if from_user_mode(trapframe) {
+        println!(
+            "[KERNEL] Page Fault - cr2: 0x{:X} - {:?}",
+            address, error_code
+        );
+        handle_exception("PAGE FAULT", trapframe);
+    }
+
+    panic!(
+        "[FATAL] Page Fault - eip: 0x{:X} - cr2: 0x{:X}",
+        trapframe.eip, address
+    );
 }
 
-// User System Call Interrupt Handlers
+fn handle_exceptions(trapframe: &mut TrapFrame) {
+    let name = EXCEPTION_NAMES[trapframe.trap_number];
 
-#[inline]
-pub extern "x86-interrupt" fn user_interrupt_switch(frame: InterruptStackFrame) {
-    unsafe {
-        // Backup the current context and trapframe of the process. This will later be
-        // restored once the trap is finished.
-        asm!("jmp trap_enter", options(nomem, nostack, preserves_flags));
+    match trapframe.trap_number {
+        NON_MASKABLE_INTERRUPT | BREAKPOINT => println!("EXCEPTION: {}\n{:#X?}", name, trapframe),
+        DOUBLE_FAULT => panic!("EXCEPTION: {}\n{:#X?}", name, trapframe),
+        PAGE_FAULT => page_fault(trapframe),
+        _ => handle_exception(name, trapframe),
     }
 }
 
+/// Single entry point for every interrupt vector. The entry stubs (trap.asm) push the same
+/// trap frame for all of them, so handlers may inspect and modify the interrupted registers.
 #[no_mangle]
-extern "C" fn user_interrupt_handler(trapframe: &mut TrapFrame) {
-    if trapframe.trap_number == 64 {
-        unsafe {
-            SCHEDULER.lock().set_trapframe(trapframe as *mut TrapFrame);
-        }
+extern "C" fn trap_handler(trapframe: &mut TrapFrame) {
+    // Traps from user mode always start at the top of the process' Kernel stack
+    if from_user_mode(trapframe) {
+        cpu_scheduler()
+            .lock()
+            .set_trapframe(trapframe as *mut TrapFrame);
+    }
 
-        handle_system_call(trapframe);
+    let irqs = PIC_1_OFFSET as usize..PIC_1_OFFSET as usize + NUM_IRQS;
+
+    match trapframe.trap_number {
+        vector if vector < NUM_EXCEPTIONS => handle_exceptions(trapframe),
+        vector if irqs.contains(&vector) => handle_irq(trapframe),
+        vector if vector == SYSCALL_VECTOR as usize => handle_system_call(trapframe),
+        LAPIC_SPURIOUS_VECTOR => handle_spurious(),
+        vector if is_masked_pic_vector(vector) => handle_masked_pic(),
+        vector => println!(
+            "[KERNEL] Unexpected Trap {} - eip: 0x{:X}",
+            vector, trapframe.eip
+        ),
     }
 }
@@ -1,4 +1,9 @@
-use core::arch::asm;
+use core::{arch::asm, sync::atomic::Ordering};
+
+use crate::{
+    smp::cpu::this_cpu,
+    x86::{defs::EFLAGS_INTERRUPT_ENABLE, helpers::read_eflags},
+};
 
 // Assembly wrapping using STI to enable interrupts
 #[inline]
@@ -8,10 +13,45 @@ pub fn enable() {
     }
 }
 
-// Assembly wrapper using CLI to disable interrupts 
+// Assembly wrapper using CLI to disable interrupts
 #[inline]
 pub fn disable() {
     unsafe {
         asm!("cli", options(nomem, nostack));
     }
 }
+
+/// Disable interrupts, remembering whether they were enabled. Calls nest: interrupts are only
+/// enabled again once every push_disable is matched by a pop_disable (xv6's pushcli/popcli).
+pub fn push_disable() {
+    let enabled = read_eflags() & EFLAGS_INTERRUPT_ENABLE != 0;
+    disable();
+
+    // Interrupts are off, so this processor stays the same from here on
+    let cpu = this_cpu();
+
+    if cpu.interrupt_depth.fetch_add(1, Ordering::SeqCst) == 0 {
+        cpu.interrupts_enabled.store(enabled, Ordering::SeqCst);
+    }
+}
+
+/// Undo a push_disable. Interrupts are enabled again by the last one, if they were enabled
+/// before the first.
+pub fn pop_disable() {
+    if read_eflags() & EFLAGS_INTERRUPT_ENABLE != 0 {
+        panic!("[FATAL] pop_disable With Interrupts Enabled");
+    }
+
+    let cpu = this_cpu();
+    let depth = cpu.interrupt_depth.load(Ordering::SeqCst);
+
+    if depth == 0 {
+        panic!("[FATAL] pop_disable Without push_disable");
+    }
+
+    cpu.interrupt_depth.store(depth - 1, Ordering::SeqCst);
+
+    if depth == 1 && cpu.interrupts_enabled.load(Ordering::SeqCst) {
+        enable();
+    }
+}
@@ -0,0 +1,70 @@
+/// I/O APIC. Receives the interrupts of the external devices, and routes each input to a vector
+/// of a chosen processor through its redirection table. ISA IRQs are usually wired to the input
+/// with the same number, although the MP tables may say otherwise (e.g. the PIT).
+/// More information can be found here: https://wiki.osdev.org/IOAPIC
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::defs::ioapic::*;
+use crate::println;
+
+/// Virtual address of the I/O APIC registers. Zero until the I/O APIC is set up.
+static IOAPIC_BASE: AtomicUsize = AtomicUsize::new(0);
+
+fn read(register: u32) -> u32 {
+    let base = IOAPIC_BASE.load(Ordering::SeqCst);
+
+    unsafe {
+        ((base + IOAPIC_REGISTER_SELECT) as *mut u32).write_volatile(register);
+        ((base + IOAPIC_WINDOW) as *const u32).read_volatile()
+    }
+}
+
+fn write(register: u32, value: u32) {
+    let base = IOAPIC_BASE.load(Ordering::SeqCst);
+
+    unsafe {
+        ((base + IOAPIC_REGISTER_SELECT) as *mut u32).write_volatile(register);
+        ((base + IOAPIC_WINDOW) as *mut u32).write_volatile(value);
+    }
+}
+
+fn write_redirection(pin: u8, low: u32, high: u32) {
+    let register = IOAPIC_REDIRECTION_TABLE + 2 * pin as u32;
+
+    write(register, low);
+    write(register + 1, high);
+}
+
+/// Route an I/O APIC input to the given vector of a processor (edge triggered, active high)
+pub fn enable_pin(pin: u8, vector: u8, apic_id: u8) {
+    write_redirection(
+        pin,
+        vector as u32,
+        (apic_id as u32) << IOAPIC_DESTINATION_SHIFT,
+    );
+}
+
+pub fn disable_pin(pin: u8) {
+    write_redirection(pin, IOAPIC_MASKED, 0);
+}
+
+/// Mask every input. Those are enabled one by one, as drivers register their IRQs.
+pub fn setup_ioapic(id: u8, address: usize) {
+    IOAPIC_BASE.store(address, Ordering::SeqCst);
+
+    let pins = ((read(IOAPIC_VERSION) >> 16) & 0xFF) + 1;
+    let actual_id = (read(IOAPIC_ID) >> 24) as u8;
+
+    if actual_id != id {
+        println!(
+            "[KERNEL] I/O APIC ID {} differs from MP tables ({})",
+            actual_id, id
+        );
+    }
+
+    for pin in 0..pins {
+        disable_pin(pin as u8);
+    }
+
+    println!("[KERNEL] I/O APIC {} Initialized: {} inputs", id, pins);
+}
@@ -0,0 +1,134 @@
+/// Device drivers register handlers for the IRQ lines of their devices at runtime. A line may be
+/// shared by several devices, in which case every registered handler runs on each interrupt.
+/// The dispatcher takes care of acknowledging the PICs, so handlers only deal with the device.
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{scheduler::defs::process::TrapFrame, threading::defs::SpinLock};
+
+use super::{
+    apic::{self, InterruptIndex, PIC_1_OFFSET},
+    defs::{
+        irq::{
+            MASKED_PIC_OFFSET, MAX_IRQ_HANDLERS, NUM_IRQS, SPURIOUS_MASTER_IRQ, SPURIOUS_SLAVE_IRQ,
+        },
+        IrqHandler,
+    },
+};
+
+type IrqTable = [[Option<IrqHandler>; MAX_IRQ_HANDLERS]; NUM_IRQS];
+
+/// Handlers of every IRQ line. Holding the lock keeps interrupts disabled, as the dispatcher
+/// takes it as well.
+static IRQ_HANDLERS: SpinLock<IrqTable> = SpinLock::new([[None; MAX_IRQ_HANDLERS]; NUM_IRQS]);
+
+/// Interrupts delivered on every IRQ line, spurious ones excluded
+static IRQ_COUNTS: [AtomicUsize; NUM_IRQS] = [const { AtomicUsize::new(0) }; NUM_IRQS];
+
+/// Spurious interrupts delivered by either PIC
+static SPURIOUS_IRQS: AtomicUsize = AtomicUsize::new(0);
+
+fn irq_line(irq: InterruptIndex) -> usize {
+    (irq.as_u8() - PIC_1_OFFSET) as usize
+}
+
+/// Register a handler for the given IRQ. Lines may be shared by up to MAX_IRQ_HANDLERS handlers.
+pub fn register_irq(irq: InterruptIndex, handler: IrqHandler) -> Result<(), &'static str> {
+    let mut handlers = IRQ_HANDLERS.lock();
+    let slot = handlers[irq_line(irq)]
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or("[ERR] IRQ Line Full")?;
+
+    *slot = Some(handler);
+    apic::enable_irq(irq);
+    Ok(())
+}
+
+/// Remove a handler previously registered for the given IRQ
+pub fn unregister_irq(irq: InterruptIndex, handler: IrqHandler) -> Result<(), &'static str> {
+    let mut handlers = IRQ_HANDLERS.lock();
+    let slot = handlers[irq_line(irq)]
+        .iter_mut()
+        .find(|slot| matches!(slot, Some(registered) if *registered as usize == handler as usize))
+        .ok_or("[ERR] IRQ Handler Not Registered")?;
+
+    *slot = None;
+
+    if handlers[irq_line(irq)].iter().all(|slot| slot.is_none()) {
+        apic::disable_irq(irq);
+    }
+
+    Ok(())
+}
+
+/// Number of (non spurious) interrupts delivered on the given IRQ
+pub fn irq_count(irq: InterruptIndex) -> usize {
+    IRQ_COUNTS[irq_line(irq)].load(Ordering::SeqCst)
+}
+
+/// Number of spurious interrupts delivered by the 8259 (on IRQ 7 and 15) or the Local APIC
+pub fn spurious_irq_count() -> usize {
+    SPURIOUS_IRQS.load(Ordering::SeqCst)
+}
+
+/// The PICs raise IRQ 7 (or 15) when the line causing an interrupt drops before it is
+/// acknowledged. Those interrupts have no In-Service bit set and must not be acknowledged.
+/// With APICs, these vectors belong to the I/O APIC, and the masked 8259 delivers its spurious
+/// interrupts on vectors of its own (see handle_masked_pic).
+fn is_spurious(line: usize) -> bool {
+    if apic::apic_enabled() {
+        return false;
+    }
+
+    match line {
+        SPURIOUS_MASTER_IRQ | SPURIOUS_SLAVE_IRQ => apic::read_isr() & (1 << line) == 0,
+        _ => false,
+    }
+}
+
+/// Dispatch an IRQ to the handlers registered on its line
+pub fn handle_irq(trapframe: &mut TrapFrame) {
+    let line = trapframe.trap_number - PIC_1_OFFSET as usize;
+
+    if is_spurious(line) {
+        SPURIOUS_IRQS.fetch_add(1, Ordering::SeqCst);
+
+        // The master did see a real interrupt on the cascade line, and still expects its EOI
+        if line == SPURIOUS_SLAVE_IRQ {
+            apic::notify_master_end_of_interrupt();
+        }
+
+        return;
+    }
+
+    IRQ_COUNTS[line].fetch_add(1, Ordering::SeqCst);
+
+    // Acknowledge before running the handlers, as some (e.g. the timer) may switch away from
+    // this context and only come back much later
+    apic::notify_end_of_interrupt(trapframe.trap_number as u8);
+
+    let handlers = IRQ_HANDLERS.lock()[line];
+
+    for handler in handlers.iter().flatten() {
+        handler(trapframe);
+    }
+}
+
+/// The Local APIC delivers spurious interrupts on their own vector, and expects no EOI for them
+pub fn handle_spurious() {
+    SPURIOUS_IRQS.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Whether the vector belongs to the 8259, masked in favour of the APICs
+pub fn is_masked_pic_vector(vector: usize) -> bool {
+    let start = MASKED_PIC_OFFSET as usize;
+    (start..start + NUM_IRQS).contains(&vector)
+}
+
+/// Interrupts from the masked 8259 can only be spurious ones. The Local APIC did not deliver
+/// them, so it must not be acknowledged (that would end the interrupt really in service), and
+/// neither must the 8259, as nothing is in service there. Its cascade line is masked as well, so
+/// the master expects no EOI for spurious interrupts of the slave either.
+pub fn handle_masked_pic() {
+    SPURIOUS_IRQS.fetch_add(1, Ordering::SeqCst);
+}
@@ -0,0 +1,142 @@
+/// Local APIC. Every processor has its own, memory mapped at the same physical address. It
+/// receives the interrupts routed by the I/O APIC (and other processors), and has a timer which
+/// replaces the PIT as the scheduler tick. Its frequency is unknown, so the timer is calibrated
+/// against the PIT first.
+/// More information can be found here: https://wiki.osdev.org/APIC
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{apic::InterruptIndex, defs::lapic::*};
+use crate::{devices::timer::pit_delay_ms, println};
+
+/// Virtual address of the Local APIC registers. Zero until the Local APIC is set up.
+static LAPIC_BASE: AtomicUsize = AtomicUsize::new(0);
+
+/// LAPIC timer ticks per second, as measured against the PIT
+static LAPIC_TIMER_FREQUENCY: AtomicUsize = AtomicUsize::new(0);
+
+pub fn lapic_present() -> bool {
+    LAPIC_BASE.load(Ordering::SeqCst) != 0
+}
+
+fn read(register: usize) -> u32 {
+    let address = LAPIC_BASE.load(Ordering::SeqCst) + register;
+    unsafe { (address as *const u32).read_volatile() }
+}
+
+fn write(register: usize, value: u32) {
+    let address = LAPIC_BASE.load(Ordering::SeqCst) + register;
+    unsafe { (address as *mut u32).write_volatile(value) };
+
+    // Wait for the write to finish, by reading
+    read(LAPIC_ID);
+}
+
+/// Local APIC ID of the running processor
+pub fn lapic_id() -> u8 {
+    if !lapic_present() {
+        return 0;
+    }
+
+    (read(LAPIC_ID) >> 24) as u8
+}
+
+/// Acknowledge the interrupt being serviced
+pub fn notify_end_of_interrupt() {
+    write(LAPIC_EOI, 0);
+}
+
+/// Wait for the previous IPI to be delivered
+fn wait_for_delivery() {
+    while read(LAPIC_ICR_LOW) & ICR_DELIVERY_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+/// Send an IPI to the processor with the given Local APIC ID
+pub fn send_ipi(apic_id: u8, command: u32) {
+    write(LAPIC_ICR_HIGH, (apic_id as u32) << ICR_DESTINATION_SHIFT);
+    write(LAPIC_ICR_LOW, command);
+    wait_for_delivery();
+}
+
+/// Wake up an Application Processor with the INIT-SIPI-SIPI sequence. The processor starts
+/// executing in real mode at the given (page aligned, below 1MiB) physical address.
+pub fn start_ap(apic_id: u8, address: usize) {
+    send_ipi(apic_id, ICR_INIT | ICR_LEVEL_TRIGGERED | ICR_ASSERT);
+    pit_delay_ms(1);
+    send_ipi(apic_id, ICR_INIT | ICR_LEVEL_TRIGGERED);
+    pit_delay_ms(10);
+
+    // Processors may miss the first STARTUP IPI, so it is sent twice
+    for _ in 0..2 {
+        send_ipi(apic_id, ICR_STARTUP | (address >> 12) as u32);
+        pit_delay_ms(1);
+    }
+}
+
+/// Count LAPIC timer ticks during a fixed PIT delay. The timer must be masked.
+fn calibrate_timer() -> usize {
+    write(LAPIC_TIMER_DIVIDE, LAPIC_TIMER_DIVIDE_BY_16);
+    write(LAPIC_TIMER_INITIAL_COUNT, u32::MAX);
+
+    pit_delay_ms(LAPIC_CALIBRATION_MS);
+
+    let elapsed = u32::MAX - read(LAPIC_TIMER_CURRENT_COUNT);
+    write(LAPIC_TIMER_INITIAL_COUNT, 0);
+
+    elapsed as usize * 1000 / LAPIC_CALIBRATION_MS
+}
+
+/// Fire the timer interrupt (IRQ 0 vector) periodically, at the given frequency
+pub fn set_timer_frequency(frequency: usize) -> usize {
+    let ticks = LAPIC_TIMER_FREQUENCY.load(Ordering::SeqCst);
+    let count = (ticks / frequency.max(1)).clamp(1, u32::MAX as usize);
+
+    write(LAPIC_TIMER_DIVIDE, LAPIC_TIMER_DIVIDE_BY_16);
+    write(
+        LAPIC_TIMER,
+        LAPIC_TIMER_PERIODIC | InterruptIndex::Timer.as_u8() as u32,
+    );
+    write(LAPIC_TIMER_INITIAL_COUNT, count as u32);
+
+    ticks / count
+}
+
+/// Enable the Local APIC of the running processor. Only interrupts from the I/O APIC (and
+/// other processors) are accepted, local interrupt lines are masked.
+pub fn setup_lapic(address: usize) {
+    LAPIC_BASE.store(address, Ordering::SeqCst);
+
+    write(
+        LAPIC_SPURIOUS,
+        LAPIC_SOFTWARE_ENABLE | LAPIC_SPURIOUS_VECTOR as u32,
+    );
+
+    write(LAPIC_TIMER, LAPIC_MASKED);
+    write(LAPIC_LINT0, LAPIC_MASKED);
+    write(LAPIC_LINT1, LAPIC_MASKED);
+    write(LAPIC_ERROR, LAPIC_MASKED);
+
+    // The performance counter entry only exists from version 4
+    if (read(LAPIC_VERSION) >> 16) & 0xFF >= 4 {
+        write(LAPIC_PERFORMANCE_COUNTER, LAPIC_MASKED);
+    }
+
+    // Clear errors (back to back writes) and pending interrupts
+    write(LAPIC_ERROR_STATUS, 0);
+    write(LAPIC_ERROR_STATUS, 0);
+    notify_end_of_interrupt();
+
+    // Accept all interrupts
+    write(LAPIC_TASK_PRIORITY, 0);
+
+    if LAPIC_TIMER_FREQUENCY.load(Ordering::SeqCst) == 0 {
+        LAPIC_TIMER_FREQUENCY.store(calibrate_timer(), Ordering::SeqCst);
+    }
+
+    println!(
+        "[KERNEL] Local APIC {} Initialized - Timer: {} Hz",
+        lapic_id(),
+        LAPIC_TIMER_FREQUENCY.load(Ordering::SeqCst)
+    );
+}
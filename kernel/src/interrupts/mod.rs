@@ -1,7 +1,11 @@
 pub mod apic;
+pub mod ioapic;
+pub mod lapic;
+pub mod mp;
 pub mod defs;
 pub mod system_call;
 pub mod idt;
 pub mod intrpt;
 pub mod interrupt_handlers;
+pub mod irq;
 
@@ -0,0 +1,185 @@
+/// MultiProcessor Specification tables. The BIOS leaves a floating pointer structure in low
+/// memory, pointing to a configuration table which lists the processors, buses and I/O APICs of
+/// the machine, as well as how ISA IRQs are wired to the I/O APIC inputs. Machines without
+/// those tables have no APIC, and fall back to the 8259 PIC.
+/// More information can be found here: https://wiki.osdev.org/Symmetric_Multiprocessing
+use core::mem::size_of;
+
+use super::defs::{irq::NUM_IRQS, mp::*, *};
+use crate::{
+    memory::defs::{EXTENDED_MEMORY, KERNEL_BASE},
+    println,
+    threading::defs::SpinLock,
+    P2V,
+};
+
+pub static MP_CONFIGURATION: SpinLock<Option<MpConfiguration>> = SpinLock::new(None);
+
+/// All bytes of an MP structure must add up to 0
+fn checksum(address: usize, length: usize) -> bool {
+    let bytes = unsafe { core::slice::from_raw_parts(address as *const u8, length) };
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+/// Search a physical range for the floating pointer. It is always 16 byte aligned.
+fn search_floating_pointer(start: usize, length: usize) -> Option<MpFloatingPointer> {
+    let size = size_of::<MpFloatingPointer>();
+
+    (P2V!(start)..P2V!(start + length))
+        .step_by(16)
+        .find(|&address| {
+            let pointer = unsafe { &*(address as *const MpFloatingPointer) };
+            pointer.signature == *MP_FLOATING_SIGNATURE && checksum(address, size)
+        })
+        .map(|address| unsafe { *(address as *const MpFloatingPointer) })
+}
+
+/// The floating pointer is either in the first KB of the EBDA, in the last KB of base memory, or
+/// in the BIOS ROM
+fn find_floating_pointer() -> Option<MpFloatingPointer> {
+    let ebda = unsafe { *(P2V!(BDA_EBDA_SEGMENT) as *const u16) as usize } << 4;
+    let base_memory = unsafe { *(P2V!(BDA_BASE_MEMORY_SIZE) as *const u16) as usize } * 1024;
+
+    (ebda != 0)
+        .then(|| search_floating_pointer(ebda, 1024))
+        .flatten()
+        .or_else(|| search_floating_pointer(base_memory.saturating_sub(1024), 1024))
+        .or_else(|| search_floating_pointer(BIOS_ROM_START, BIOS_ROM_END - BIOS_ROM_START))
+}
+
+impl MpConfiguration {
+    pub const fn empty() -> Self {
+        MpConfiguration {
+            lapic_address: 0,
+            ioapic_id: 0,
+            ioapic_address: 0,
+            processors: [0; MAX_CPUS],
+            num_processors: 0,
+            bsp_apic_id: 0,
+            irq_pins: [0; NUM_IRQS],
+            imcr_present: false,
+        }
+    }
+
+    /// Parse the configuration table announced by the floating pointer. Only the first I/O APIC
+    /// is used.
+    pub fn from_mp_tables() -> Result<Self, &'static str> {
+        let pointer = find_floating_pointer().ok_or("[ERR] MP Floating Pointer Not Found")?;
+        let table = pointer.config_table as usize;
+
+        // Only low memory is guaranteed to be mapped this early
+        if table == 0 || table >= EXTENDED_MEMORY {
+            return Err("[ERR] MP Configuration Table Unreachable");
+        }
+
+        let header = unsafe { *(P2V!(table) as *const MpConfigHeader) };
+
+        if header.signature != *MP_CONFIG_SIGNATURE
+            || (header.version != 1 && header.version != 4)
+            || !checksum(P2V!(table), header.length as usize)
+        {
+            return Err("[ERR] Invalid MP Configuration Table");
+        }
+
+        let mut config = MpConfiguration::empty();
+        let mut isa_bus = None;
+        let mut ioapic_found = false;
+
+        config.lapic_address = header.lapic_address as usize;
+        config.imcr_present = pointer.features & MP_IMCR_PRESENT != 0;
+
+        // ISA IRQs are identity mapped, unless an interrupt entry says otherwise
+        for (irq, pin) in config.irq_pins.iter_mut().enumerate() {
+            *pin = irq as u8;
+        }
+
+        let mut entry = P2V!(table) + size_of::<MpConfigHeader>();
+        let end = P2V!(table) + header.length as usize;
+
+        while entry < end {
+            match unsafe { *(entry as *const u8) } {
+                MP_PROCESSOR => {
+                    let processor = unsafe { *(entry as *const MpProcessor) };
+
+                    if processor.flags & MP_PROCESSOR_ENABLED != 0
+                        && config.num_processors < MAX_CPUS
+                    {
+                        if processor.flags & MP_PROCESSOR_BSP != 0 {
+                            config.bsp_apic_id = processor.apic_id;
+                        }
+
+                        config.processors[config.num_processors] = processor.apic_id;
+                        config.num_processors += 1;
+                    }
+
+                    entry += size_of::<MpProcessor>();
+                }
+                MP_BUS => {
+                    let bus = unsafe { *(entry as *const MpBus) };
+
+                    if bus.name == *MP_BUS_ISA {
+                        isa_bus = Some(bus.id);
+                    }
+
+                    entry += size_of::<MpBus>();
+                }
+                MP_IOAPIC => {
+                    let ioapic = unsafe { *(entry as *const MpIoApic) };
+
+                    if !ioapic_found {
+                        config.ioapic_id = ioapic.id;
+                        config.ioapic_address = ioapic.address as usize;
+                        ioapic_found = true;
+                    }
+
+                    entry += size_of::<MpIoApic>();
+                }
+                MP_IO_INTERRUPT => {
+                    let interrupt = unsafe { *(entry as *const MpInterrupt) };
+                    let irq = interrupt.source_irq as usize;
+
+                    // Buses are listed before interrupts
+                    if interrupt.interrupt_type == MP_INTERRUPT_VECTORED
+                        && Some(interrupt.source_bus) == isa_bus
+                        && irq < NUM_IRQS
+                    {
+                        config.irq_pins[irq] = interrupt.destination_pin;
+                    }
+
+                    entry += size_of::<MpInterrupt>();
+                }
+                MP_LOCAL_INTERRUPT => entry += size_of::<MpInterrupt>(),
+                _ => return Err("[ERR] Unknown MP Configuration Entry"),
+            }
+        }
+
+        if config.num_processors == 0 || !ioapic_found {
+            return Err("[ERR] MP Configuration Without APICs");
+        }
+
+        Ok(config)
+    }
+
+    pub fn processors(&self) -> &[u8] {
+        &self.processors[..self.num_processors]
+    }
+}
+
+/// Look for the MP tables. Returns whether the machine has APICs.
+pub fn setup_mp() -> bool {
+    match MpConfiguration::from_mp_tables() {
+        Ok(config) => {
+            println!(
+                "[KERNEL] MP Tables: {} CPU(s) - LAPIC 0x{:X} - IOAPIC 0x{:X}",
+                config.num_processors, config.lapic_address, config.ioapic_address
+            );
+
+            *MP_CONFIGURATION.lock() = Some(config);
+            true
+        }
+        Err(error) => {
+            println!("{}", error);
+            false
+        }
+    }
+}
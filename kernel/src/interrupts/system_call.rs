@@ -1,66 +1,173 @@
-use core::arch::asm;
+use abi::errno::{Errno, SystemCallResult};
+use alloc::{string::String, vec::Vec};
+use core::mem::size_of;
 use lazy_static::lazy_static;
 
 use crate::{
     interrupts::defs::system_call::*,
+    memory::uaccess::{read_from_user, strncpy_from_user, write_to_user},
     println,
-    scheduler::{defs::process::TrapFrame, scheduler::SCHEDULER},
+    scheduler::{
+        defs::process::{TrapFrame, MAX_ARGS, MAX_ARG_LENGTH},
+        process::{exec, exit, fork, wait},
+    },
 };
 
-/// If a call to an undefined System Call happens, panic and exit.
-/// TODO: Change this to an exit of the process instead of killing the system.
-fn panic_undefined_syscall() {
-    panic!("[FATAL] Undefined System Call");
-}
+/// Every System Call handler receives the trapframe of the caller, where its arguments are, and
+/// returns either a value or an error. Both are handed back to the caller in eax.
+type SystemCall = fn(&mut TrapFrame) -> SystemCallResult;
 
 lazy_static! {
-    /// Add your own System Calls here. Notice parameters use the System V ABI calling convention,
-    /// so edi, esi, edx, and ecx registers are used to pass the first four parameters to parameters.
-    /// Functions are passed as addresses in order to avoid Rust parameter validation.
-    static ref SYSTEM_CALLS: [usize; NUM_SYS_CALLS] = {
-        let panic_handler_address = panic_undefined_syscall as *const () as usize;
-        let mut sys_calls = [panic_handler_address; NUM_SYS_CALLS];
+    /// Add your own System Calls here. Numbers without a handler fail with ENOSYS.
+    static ref SYSTEM_CALLS: [Option<SystemCall>; NUM_SYS_CALLS] = {
+        let mut sys_calls: [Option<SystemCall>; NUM_SYS_CALLS] = [None; NUM_SYS_CALLS];
 
-        sys_calls[PRINT_TRAPFRAME_SYSCALL] = print_trapframe as *const () as usize;
+        sys_calls[PRINT_TRAPFRAME_SYSCALL] = Some(print_trapframe);
+        sys_calls[FORK_SYSCALL] = Some(sys_fork);
+        sys_calls[EXEC_SYSCALL] = Some(sys_exec);
+        sys_calls[EXIT_SYSCALL] = Some(sys_exit);
+        sys_calls[WAIT_SYSCALL] = Some(sys_wait);
+        sys_calls[WAITPID_SYSCALL] = Some(sys_waitpid);
 
         sys_calls
     };
 }
 
-/// Every System Call passes through this handler. The trapframe is passed to facilitate loading
-/// the ABI registers and getting the system call number in eax.
-pub fn handle_system_call(trapframe: &TrapFrame) {
-    let system_call_number = trapframe.eax;
+/// System Call arguments, following the ABI described in abi::syscall
+impl TrapFrame {
+    pub fn system_call_number(&self) -> usize {
+        self.eax
+    }
 
-    if system_call_number > NUM_SYS_CALLS - 1 {
-        panic_undefined_syscall();
+    /// Raw value of an argument: edi, esi, edx and ecx hold arguments 0 to 3
+    pub fn argument(&self, index: usize) -> usize {
+        match index {
+            0 => self.edi,
+            1 => self.esi,
+            2 => self.edx,
+            3 => self.ecx,
+            _ => panic!("[FATAL] System Calls take at most 4 arguments"),
+        }
     }
 
-    let system_call_fn = SYSTEM_CALLS[system_call_number];
+    pub fn argument_isize(&self, index: usize) -> isize {
+        self.argument(index) as isize
+    }
+
+    /// Argument holding a user space address. Notice it is not validated in any way.
+    pub fn argument_pointer<T>(&self, index: usize) -> *mut T {
+        self.argument(index) as *mut T
+    }
+
+    /// Hand the result of a System Call back to the caller
+    pub fn set_return_value(&mut self, result: SystemCallResult) {
+        self.eax = match result {
+            Ok(value) => value,
+            Err(errno) => errno.to_return_value(),
+        };
+    }
+}
+
+/// Every System Call passes through this handler. The System Call number is taken from eax,
+/// and the result of the handler is written back to eax.
+pub fn handle_system_call(trapframe: &mut TrapFrame) {
+    let system_call = SYSTEM_CALLS
+        .get(trapframe.system_call_number())
+        .copied()
+        .flatten();
 
-    unsafe {
-        asm!("
-        pusha
+    let result = match system_call {
+        Some(system_call) => system_call(trapframe),
+        None => Err(Errno::ENOSYS),
+    };
+
+    trapframe.set_return_value(result);
+}
+
+pub fn print_trapframe(trapframe: &mut TrapFrame) -> SystemCallResult {
+    println!("{:#?}", trapframe);
+    Ok(0)
+}
 
-        mov eax, {trapframe}
+/// Duplicate the calling process. The parent gets the PID of the child, while the child
+/// gets 0.
+pub fn sys_fork(_trapframe: &mut TrapFrame) -> SystemCallResult {
+    unsafe { fork() }.map_err(|err| {
+        println!("[KERNEL] Fork Failed: {}", err);
+        Errno::ENOMEM
+    })
+}
+
+/// Read a NUL-terminated string of at most MAX_ARG_LENGTH bytes from the user space
+fn fetch_user_string(address: usize) -> Result<String, Errno> {
+    let mut buffer = [0u8; MAX_ARG_LENGTH];
+    let length = strncpy_from_user(&mut buffer, address)?;
+
+    core::str::from_utf8(&buffer[..length])
+        .map(String::from)
+        .map_err(|_| Errno::EINVAL)
+}
 
-        mov edi, [eax]
-        mov esi, [eax + 4]
-        mov ecx, [eax + 24]
-        mov edx, [eax + 20]
+/// Read a NULL-terminated array of strings (such as argv) from the user space
+fn fetch_user_strings(address: usize) -> Result<Vec<String>, Errno> {
+    let mut strings = Vec::new();
 
-        mov eax, ebx
-        call eax
-        
-        popa
-        ",
-            trapframe = in(reg) trapframe as *const TrapFrame as usize,
-            in("ebx") system_call_fn
-        );
+    for index in 0..=MAX_ARGS {
+        let pointer_address = address
+            .checked_add(index * size_of::<usize>())
+            .ok_or(Errno::EFAULT)?;
+        let pointer: usize = read_from_user(pointer_address)?;
+
+        if pointer == 0 {
+            return Ok(strings);
+        }
+
+        strings.push(fetch_user_string(pointer)?);
     }
+
+    Err(Errno::E2BIG)
 }
 
-pub fn print_trapframe() {
-    let trapframe = unsafe { SCHEDULER.lock().get_trapframe().unwrap() };
-    println!("{:#?}", unsafe { (*trapframe).clone() });
+/// Replace the calling process with the program at the path in the first argument, passing it
+/// the NULL-terminated argv array in the second one. Only returns on failure.
+pub fn sys_exec(trapframe: &mut TrapFrame) -> SystemCallResult {
+    let path = fetch_user_string(trapframe.argument(0))?;
+    let argv = fetch_user_strings(trapframe.argument(1))?;
+
+    unsafe { exec(&path, &argv) }?;
+    Ok(0)
+}
+
+/// Terminate the calling process with the exit status in the first argument
+pub fn sys_exit(trapframe: &mut TrapFrame) -> SystemCallResult {
+    unsafe { exit(trapframe.argument(0)) };
+}
+
+/// Wait for a child to exit (any child if pid is None). Returns its PID, and writes its exit
+/// status to status_address (unless it is NULL).
+fn wait_child(pid: Option<usize>, status_address: *mut usize) -> SystemCallResult {
+    let (pid, status) = unsafe { wait(pid) }.map_err(|_| Errno::ECHILD)?;
+
+    if !status_address.is_null() {
+        write_to_user(status_address as usize, &status)?;
+    }
+
+    Ok(pid)
+}
+
+/// Wait for any child to exit, writing its exit status to the address in the first argument
+pub fn sys_wait(trapframe: &mut TrapFrame) -> SystemCallResult {
+    wait_child(None, trapframe.argument_pointer(0))
+}
+
+/// Wait for the child with the PID in the first argument to exit (any child if -1), writing its
+/// exit status to the address in the second argument
+pub fn sys_waitpid(trapframe: &mut TrapFrame) -> SystemCallResult {
+    let pid = match trapframe.argument_isize(0) {
+        -1 => None,
+        pid if pid < 0 => return Err(Errno::EINVAL),
+        pid => Some(pid as usize),
+    };
+
+    wait_child(pid, trapframe.argument_pointer(1))
 }
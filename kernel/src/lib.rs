@@ -6,10 +6,12 @@
 #[macro_use]
 
 pub mod devices;
+pub mod elf;
 pub mod interrupts;
 pub mod memory;
 pub mod misc;
 pub mod scheduler;
+pub mod smp;
 pub mod structures;
 pub mod threading;
 pub mod x86;
@@ -29,6 +31,10 @@ pub unsafe extern "C" fn _start() -> ! {
     devices::debug::debug_init();
     misc::logo::print_logo();
 
+    // Discover Physical Memory
+    memory::e820::setup_memory_map();
+    memory::frame::setup_frame_allocator();
+
     // Setup Segmentation and Virtual Memory
     memory::vm::setup_vm();
     memory::gdt::setup_gdt();
@@ -37,8 +43,9 @@ pub unsafe extern "C" fn _start() -> ! {
     // Setup Interrupts
     interrupts::idt::setup_idt();
 
-    // Setup PIC and Enable Interrupts
-    unsafe {interrupts::apic::PICS.lock().initialize()};
+    // Setup Interrupt Controller and Timer, and Enable Interrupts
+    interrupts::apic::setup_interrupt_controller();
+    devices::timer::setup_timer();
     interrupts::intrpt::enable();
 
     // Initialize IDE Device
@@ -47,19 +54,22 @@ pub unsafe extern "C" fn _start() -> ! {
     // Enable Buffer Caching
     fs::bio::setup_bcache();
 
+    // Start the other processors
+    smp::startup::setup_smp();
+
     // Scheduler
-    // scheduler::process::spawn_init_process();
-    // scheduler::scheduler::setup_scheduler();
+    scheduler::process::spawn_init_process().expect("[FATAL] Failed to Spawn Init");
+    scheduler::scheduler::setup_scheduler();
 
     // Should never proceeed
-    // panic!("[FATAL] Returned from Scheduler");
-    loop {}
+    panic!("[FATAL] Returned from Scheduler");
 }
 
 // Once the Kernel panics, enter an infinite loop
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
-    print!("{}", _info);
+    interrupts::intrpt::disable();
+    devices::debug::_panic_print(format_args!("{}", _info));
     loop {}
 }
 
@@ -51,13 +51,14 @@ macro_rules! PAGE_DIR_INDEX {
 }
 
 /// GDT Definitions
-pub const N_DESCRIPTORS: usize = 6;
+pub const N_DESCRIPTORS: usize = 7;
 
 pub const KERNEL_CODE_SEG_ENTRY: u16 = 1;
 pub const KERNEL_DATA_SEG_ENTRY: u16 = 2;
 pub const USER_CODE_SEG_ENTRY: u16 = 3;
 pub const USER_DATA_SEG_ENTRY: u16 = 4;
 pub const TASK_SWITCH_SEG_ENTRY: u16 = 5;
+pub const CPU_SEG_ENTRY: u16 = 6; // Per-CPU data, reached through GS
 
 pub const GDT_FLAG_L: u8 = 0x2;
 pub const GDT_FLAG_DB: u8 = 0x4;
@@ -81,23 +82,68 @@ pub const DEVICE_SPACE: usize = 0xFE000000;
 pub const PHYSICAL_DEVICE_SPACE: usize = V2P!(DEVICE_SPACE);
 pub const KERNEL_BASE: usize = 0x80000000;
 pub const KERNEL_LINK: usize = KERNEL_BASE + EXTENDED_MEMORY;
+pub const KERNEL_HEAP_START: usize = 0xF0000000; // Virtual range reserved for the Kernel heap
+pub const KERNEL_HEAP_END: usize = 0xF8000000;
+pub const PHYSICAL_LIMIT: usize = V2P!(KERNEL_HEAP_START); // Highest physical address mapped
+pub const USER_STACK_TOP: usize = KERNEL_BASE; // User stack grows down from the top of user space
+pub const USER_STACK_PAGES: usize = 4;
 
 pub const PAGE_DIR_SHIFT: usize = 22;
 pub const PAGE_TABLE_SHIFT: usize = 12;
 
-pub const PTE_P: usize = 0x001;
-pub const PTE_W: usize = 0x002;
-pub const PTE_U: usize = 0x004;
-pub const PTE_PS: usize = 0x080;
+pub const PAGE_TABLE_ENTRIES: usize = 1024;
+pub const PAGE_TABLE_COVERAGE: usize = PAGE_SIZE * PAGE_TABLE_ENTRIES; // Bytes mapped by a table
+pub const PAGE_ADDRESS_MASK: usize = !0xFFF;
+
+/// E820 Definitions
+pub const E820_MAP_ADDRESS: usize = 0xE820; // Filled by the bootloader (see bootloader/src/e820.asm)
+pub const E820_MAX_ENTRIES: usize = 32;
+pub const E820_TYPE_USABLE: u32 = 1;
+pub const E820_TYPE_ACPI_RECLAIMABLE: u32 = 3;
+pub const E820_TYPE_ACPI_NVS: u32 = 4;
+pub const E820_TYPE_BAD: u32 = 5;
+
+pub const DEFAULT_PHYSICAL_TOP: usize = 0xE000000; // Used when the BIOS provides no memory map
+pub const MAX_LAYOUT_ENTRIES: usize = E820_MAX_ENTRIES + 4;
+
+/// Frame Allocator Definitions
+pub const MAX_FRAME_REFERENCES: u8 = u8::MAX;
 
 /// Heap Definitions
 pub const HEAP_PAGES: usize = 25;
 pub const STACK_PAGES: usize = 4;
 
+pub const HEAP_SIZE_CLASSES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+pub const HEAP_SLAB_SIZE: usize = PAGE_SIZE; // Chunk carved from the free list to refill a class
+pub const HEAP_GROW_PAGES: usize = 16; // Minimum number of pages mapped when the heap grows
+
 pub struct LinkedListAllocator {
     pub head: StaticLinkedListNode,
 }
 
+/// Kernel heap allocator. Small objects are served from size-class caches, while larger ones
+/// (and the slabs backing the caches) come from a coalescing, address-ordered free list.
+pub struct HeapAllocator {
+    pub top: usize, // End of the mapped part of the heap range
+    pub free_list: LinkedListAllocator,
+    pub size_classes: [Option<&'static mut StaticLinkedListNode>; HEAP_SIZE_CLASSES.len()],
+    pub stats: HeapStats,
+}
+
+/// Heap usage counters. Free list related fields are only filled when read through heap_stats.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HeapStats {
+    pub size: usize,               // Bytes managed by the heap
+    pub used: usize,               // Bytes handed out to callers
+    pub cached: usize,             // Bytes sitting free in the size-class caches
+    pub free: usize,               // Bytes in the free list
+    pub free_blocks: usize,        // Number of blocks in the free list
+    pub largest_free_block: usize, // Size of the largest block in the free list
+    pub allocations: usize,        // Successful allocations
+    pub deallocations: usize,      // Deallocations
+    pub failures: usize,           // Allocations that returned null
+}
+
 #[derive(Debug, Clone)]
 pub struct GlobalDescriptorTable {
     pub table: [u64; N_DESCRIPTORS], // Segment Descriptor List
@@ -111,25 +157,109 @@ pub struct GlobalDescriptorTablePointer {
     pub base: u64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct MemoryLayoutEntry {
     pub virt: *const usize, // Start of the virtual address
     pub phys_start: usize,  // Start of the physical address
     pub phys_end: usize,    // End of the physical address
-    pub perm: usize,        // Permission flags
+    pub perm: PageFlags,    // Permission flags
 }
 
+/// Kernel memory layout, built at boot time from the physical memory map
+#[derive(Debug)]
+pub struct MemoryLayout {
+    pub entries: [MemoryLayoutEntry; MAX_LAYOUT_ENTRIES],
+    pub len: usize,
+}
+
+/// A physical frame, addressed through its virtual address above KERNEL_BASE
 #[derive(Debug, Clone, Copy)]
 pub struct Page {
-    pub address: *const usize, // TODO: Revamp how pages work
+    pub address: *const usize,
 }
 
+/// Entry of a page directory or of a page table: the physical address of the next level
+/// (or of the mapped frame) along with its PageFlags
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct PageTableEntry {
+    pub entry: usize,
+}
+
+/// Page directories and page tables share the same format on x86: a page-aligned array of
+/// 1024 entries
+#[repr(C, align(4096))]
+pub struct PageTable {
+    pub entries: [PageTableEntry; PAGE_TABLE_ENTRIES],
+}
+
+/// Handle to a page directory, the root of an address space
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageDirectory {
+    pub table: *mut PageTable, // Virtual address (above KERNEL_BASE) of the directory
+}
+
+/// Bitmap based physical frame allocator (see frame.rs)
 #[derive(Debug)]
-pub struct MemoryRegion {
-    pub start: usize,
-    pub index: usize,
-    pub end: usize,
+pub struct FrameAllocator {
+    pub bitmap: *mut u32, // One bit per physical frame (1 = used)
+    pub references: *mut u8, // Number of mappings sharing each used frame
+    pub frames: usize,    // Number of frames tracked by the bitmap
+    pub free: usize,      // Number of free frames
+    pub hint: usize,      // Every frame below the hint is known to be used
+}
+
+/// Entry of the memory map as reported by the BIOS (INT 0x15, EAX = 0xE820)
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct E820Entry {
+    pub base: u64,   // Start of the physical range
+    pub length: u64, // Size of the physical range in bytes
+    pub kind: u32,   // Range type (usable, reserved, ACPI, etc)
+    pub acpi: u32,   // ACPI 3.0 extended attributes
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhysicalRegionKind {
+    Usable,
+    Reserved,
+    AcpiReclaimable,
+    AcpiNvs,
+    Bad,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PhysicalRegion {
+    pub start: usize, // Start of the physical address (page aligned)
+    pub end: usize,   // End of the physical address (page aligned)
+    pub kind: PhysicalRegionKind,
+}
+
+/// Physical memory map, sorted by start address
+#[derive(Debug)]
+pub struct PhysicalMemoryMap {
+    pub regions: [PhysicalRegion; E820_MAX_ENTRIES],
+    pub len: usize,
+}
+
+bitflags! {
+    /// Permission and status bits of page directory and page table entries
+    /// See more at https://wiki.osdev.org/Paging#Page_Directory
+    pub struct PageFlags: usize {
+        const PRESENT           = 1 << 0;
+        const WRITABLE          = 1 << 1;
+        const USER              = 1 << 2;
+        const WRITE_THROUGH     = 1 << 3;
+        const CACHE_DISABLE     = 1 << 4;
+        const ACCESSED          = 1 << 5;
+        const DIRTY             = 1 << 6;
+        const HUGE_PAGE         = 1 << 7;
+        const GLOBAL            = 1 << 8;
+
+        // Bits 9 - 11 are available to the Kernel
+        const COPY_ON_WRITE     = 1 << 9; // Shared frame, copied on the first write
+    }
 }
 
 bitflags! {
@@ -0,0 +1,195 @@
+/// Physical memory map. Before jumping to protected mode, the bootloader asks the BIOS for
+/// the list of physical ranges (INT 0x15, EAX = 0xE820) and stores them at E820_MAP_ADDRESS,
+/// a 16-bit entry counter followed by the entries themselves. Here, we decode this list into
+/// page aligned regions, so the rest of the Kernel knows which ranges hold real, usable RAM
+/// and which ones are holes reserved by the firmware and devices.
+/// More information can be found here: https://wiki.osdev.org/Detecting_Memory_(x86)
+use super::defs::*;
+use super::mem::PHYSICAL_TOP;
+use crate::{println, threading::defs::SpinLock, P2V, ROUND_DOWN, ROUND_UP};
+
+pub static PHYSICAL_MEMORY_MAP: SpinLock<PhysicalMemoryMap> =
+    SpinLock::new(PhysicalMemoryMap::new());
+
+impl PhysicalRegionKind {
+    pub fn from_e820(kind: u32) -> Self {
+        match kind {
+            E820_TYPE_USABLE => PhysicalRegionKind::Usable,
+            E820_TYPE_ACPI_RECLAIMABLE => PhysicalRegionKind::AcpiReclaimable,
+            E820_TYPE_ACPI_NVS => PhysicalRegionKind::AcpiNvs,
+            E820_TYPE_BAD => PhysicalRegionKind::Bad,
+            _ => PhysicalRegionKind::Reserved,
+        }
+    }
+}
+
+impl PhysicalRegion {
+    pub const fn empty() -> Self {
+        PhysicalRegion {
+            start: 0,
+            end: 0,
+            kind: PhysicalRegionKind::Reserved,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_usable(&self) -> bool {
+        self.kind == PhysicalRegionKind::Usable
+    }
+
+    pub fn contains(&self, address: usize) -> bool {
+        address >= self.start && address < self.end
+    }
+}
+
+impl PhysicalMemoryMap {
+    pub const fn new() -> Self {
+        PhysicalMemoryMap {
+            regions: [PhysicalRegion::empty(); E820_MAX_ENTRIES],
+            len: 0,
+        }
+    }
+
+    /// Decode the entries left by the bootloader. Ranges above what the Kernel can address
+    /// are dropped, and usable ranges are shrunk to page boundaries so that a partial page
+    /// is never handed out.
+    pub fn from_e820() -> Self {
+        let mut map = PhysicalMemoryMap::new();
+        let count = unsafe { *(P2V!(E820_MAP_ADDRESS) as *const u16) } as usize;
+        let entries = P2V!(E820_MAP_ADDRESS + 4) as *const E820Entry;
+
+        for index in 0..count.min(E820_MAX_ENTRIES) {
+            let entry = unsafe { entries.add(index).read_unaligned() };
+            let limit = PHYSICAL_LIMIT as u64;
+
+            if entry.length == 0 || entry.base >= limit {
+                continue;
+            }
+
+            let start = entry.base as usize;
+            let end = (entry.base.saturating_add(entry.length)).min(limit) as usize;
+            let kind = PhysicalRegionKind::from_e820(entry.kind);
+
+            let (start, end) = match kind {
+                PhysicalRegionKind::Usable => {
+                    (ROUND_UP!(start, PAGE_SIZE), ROUND_DOWN!(end, PAGE_SIZE))
+                }
+                _ => (ROUND_DOWN!(start, PAGE_SIZE), ROUND_UP!(end, PAGE_SIZE)),
+            };
+
+            if start < end {
+                map.add(PhysicalRegion { start, end, kind });
+            }
+        }
+
+        map
+    }
+
+    /// Map used when the BIOS did not report anything: conventional memory and everything from
+    /// the extended memory up to DEFAULT_PHYSICAL_TOP is assumed to be usable.
+    pub fn fallback() -> Self {
+        let mut map = PhysicalMemoryMap::new();
+
+        map.add(PhysicalRegion {
+            start: 0,
+            end: 0x9F000,
+            kind: PhysicalRegionKind::Usable,
+        });
+        map.add(PhysicalRegion {
+            start: EXTENDED_MEMORY,
+            end: DEFAULT_PHYSICAL_TOP,
+            kind: PhysicalRegionKind::Usable,
+        });
+
+        map
+    }
+
+    /// Insert a region, keeping the map sorted by start address
+    pub fn add(&mut self, region: PhysicalRegion) {
+        if self.len == self.regions.len() {
+            return;
+        }
+
+        let mut index = self.len;
+        while index > 0 && self.regions[index - 1].start > region.start {
+            self.regions[index] = self.regions[index - 1];
+            index -= 1;
+        }
+
+        self.regions[index] = region;
+        self.len += 1;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PhysicalRegion> {
+        self.regions[..self.len].iter()
+    }
+
+    pub fn usable(&self) -> impl Iterator<Item = &PhysicalRegion> {
+        self.iter().filter(|region| region.is_usable())
+    }
+
+    /// Usable region containing the given physical address, if any. Usable ranges overlapping
+    /// a reserved one are not trusted.
+    pub fn usable_region(&self, address: usize) -> Option<PhysicalRegion> {
+        let region = *self.usable().find(|region| region.contains(address))?;
+        let overlaps_reserved = self.iter().any(|other| {
+            !other.is_usable() && other.start < region.end && region.start < other.end
+        });
+
+        match overlaps_reserved {
+            true => None,
+            false => Some(region),
+        }
+    }
+
+    /// End of the highest usable region
+    pub fn top(&self) -> usize {
+        self.usable().map(|region| region.end).max().unwrap_or(0)
+    }
+
+    pub fn usable_size(&self) -> usize {
+        self.usable().map(|region| region.size()).sum()
+    }
+
+    pub fn reserved_size(&self) -> usize {
+        self.iter()
+            .filter(|region| !region.is_usable())
+            .map(|region| region.size())
+            .sum()
+    }
+
+    pub fn print_summary(&self) {
+        for region in self.iter() {
+            println!(
+                "[KERNEL]     0x{:08X} - 0x{:08X} {:?}",
+                region.start, region.end, region.kind
+            );
+        }
+
+        println!(
+            "[KERNEL] Usable Memory: {} KiB - Reserved Memory: {} KiB",
+            self.usable_size() / 1024,
+            self.reserved_size() / 1024
+        );
+    }
+}
+
+/// Build the physical memory map from the data left by the bootloader and derive PHYSICAL_TOP
+/// from it. Must run before any page is allocated, since page allocation relies on this map.
+pub fn setup_memory_map() {
+    let mut map = PhysicalMemoryMap::from_e820();
+
+    if map.top() <= EXTENDED_MEMORY {
+        println!("[KERNEL] No E820 Memory Map Found, Assuming Default Layout");
+        map = PhysicalMemoryMap::fallback();
+    }
+
+    println!("[KERNEL] Physical Memory Map");
+    map.print_summary();
+
+    unsafe { *PHYSICAL_TOP.lock() = map.top() };
+    *PHYSICAL_MEMORY_MAP.lock() = map;
+}
@@ -0,0 +1,260 @@
+/// Physical frame allocator. Every physical frame (a page-sized chunk of physical memory) is
+/// tracked by a single bit of a bitmap (1 = used, 0 = free). The bitmap itself is stored right
+/// after the end of the Kernel image, so it can be used before the heap (or even the final page
+/// tables) exist. Frames that are not part of a usable range of the physical memory map, the
+/// Kernel image and the bitmap are marked as used from the start, so they are never handed out.
+/// Right after the bitmap, a byte per frame counts how many mappings share it, so frames shared
+/// copy-on-write are only released once their last mapping is gone.
+use core::ptr::null_mut;
+
+use super::{defs::*, e820::PHYSICAL_MEMORY_MAP, mem::PHYSICAL_TOP};
+use crate::{
+    memory::mem::memset, println, threading::defs::SpinLock, P2V, ROUND_UP, V2P,
+};
+
+extern "C" {
+    static KERNEL_END: u8;
+}
+
+pub static FRAME_ALLOCATOR: SpinLock<FrameAllocator> = SpinLock::new(FrameAllocator::new());
+
+impl FrameAllocator {
+    pub const fn new() -> Self {
+        FrameAllocator {
+            bitmap: null_mut(),
+            references: null_mut(),
+            frames: 0,
+            free: 0,
+            hint: 0,
+        }
+    }
+
+    /// Place the bitmap and reference counts at the given (virtual) address and mark every
+    /// frame as used. Frames become available once they are released with mark_free.
+    ///
+    /// # Safety
+    /// bitmap_address must point to metadata_size(frames) writable bytes that nothing else uses
+    /// for as long as the allocator does.
+    pub unsafe fn init(&mut self, bitmap_address: usize, frames: usize) {
+        let bitmap_size = Self::bitmap_size(frames);
+
+        self.bitmap = bitmap_address as *mut u32;
+        self.references = (bitmap_address + bitmap_size) as *mut u8;
+        self.frames = frames;
+        self.free = 0;
+        self.hint = 0;
+
+        memset(bitmap_address, 0xFF, bitmap_size);
+        memset(self.references as usize, 0, frames);
+    }
+
+    /// Size in bytes of a bitmap tracking the given number of frames
+    fn bitmap_size(frames: usize) -> usize {
+        ROUND_UP!(frames, 32) / 8
+    }
+
+    /// Size in bytes (rounded to a page) of the bitmap and reference counts tracking the given
+    /// number of frames
+    pub fn metadata_size(frames: usize) -> usize {
+        ROUND_UP!(Self::bitmap_size(frames) + frames, PAGE_SIZE)
+    }
+
+    fn is_used(&self, frame: usize) -> bool {
+        unsafe { (*self.bitmap.add(frame / 32) & (1 << (frame % 32))) != 0 }
+    }
+
+    fn set_used(&mut self, frame: usize, used: bool) {
+        unsafe {
+            let word = self.bitmap.add(frame / 32);
+            match used {
+                true => *word |= 1 << (frame % 32),
+                false => *word &= !(1 << (frame % 32)),
+            }
+        }
+    }
+
+    /// Release a physical range (start and end must be page aligned) to the allocator
+    pub fn mark_free(&mut self, start: usize, end: usize) {
+        for frame in (start / PAGE_SIZE)..(end / PAGE_SIZE).min(self.frames) {
+            if self.is_used(frame) {
+                self.set_used(frame, false);
+                self.free += 1;
+            }
+        }
+    }
+
+    /// Allocate a run of contiguous frames, returning the first page of the run. The search is
+    /// first-fit, starting at the lowest frame that might be free.
+    pub fn allocate(&mut self, count: usize) -> Result<Page, &'static str> {
+        if count == 0 || count > self.free {
+            return Err("[ERR] Failure to Allocate Page");
+        }
+
+        let mut start = self.hint;
+        let mut length = 0;
+        let mut frame = self.hint;
+
+        while frame < self.frames {
+            // Skip whole used words at once, they are the common case in a busy system
+            if frame.is_multiple_of(32) && unsafe { *self.bitmap.add(frame / 32) } == !0 {
+                length = 0;
+                frame += 32;
+                continue;
+            }
+
+            if self.is_used(frame) {
+                length = 0;
+                frame += 1;
+                continue;
+            }
+
+            if length == 0 {
+                start = frame;
+            }
+
+            length += 1;
+            frame += 1;
+
+            if length == count {
+                for frame in start..start + count {
+                    self.set_used(frame, true);
+                    unsafe { *self.references.add(frame) = 1 };
+                }
+
+                self.free -= count;
+                if start == self.hint {
+                    self.hint = start + count;
+                }
+
+                return Ok(Page {
+                    address: P2V!(start * PAGE_SIZE) as *const usize,
+                });
+            }
+        }
+
+        Err("[ERR] Failure to Allocate Page")
+    }
+
+    /// Return a run of contiguous frames (previously allocated together or not) to the allocator.
+    /// Shared frames just lose a reference, and are only released once the last one is gone.
+    pub fn deallocate(&mut self, page: Page, count: usize) {
+        let first = V2P!(page.address as usize) / PAGE_SIZE;
+
+        for frame in first..first + count {
+            if frame >= self.frames || !self.is_used(frame) {
+                panic!(
+                    "[FATAL] Frame 0x{:X} freed but not allocated",
+                    frame * PAGE_SIZE
+                );
+            }
+
+            let references = unsafe { &mut *self.references.add(frame) };
+            *references = references.saturating_sub(1);
+
+            if *references == 0 {
+                self.set_used(frame, false);
+                self.free += 1;
+                self.hint = self.hint.min(frame);
+            }
+        }
+    }
+
+    /// Add a reference to an allocated frame, so it is only released once every mapping sharing
+    /// it is gone. Fails if the frame is shared by too many mappings already.
+    pub fn share(&mut self, page: Page) -> Result<(), &'static str> {
+        let frame = V2P!(page.address as usize) / PAGE_SIZE;
+
+        if frame >= self.frames || !self.is_used(frame) {
+            panic!(
+                "[FATAL] Frame 0x{:X} shared but not allocated",
+                frame * PAGE_SIZE
+            );
+        }
+
+        let references = unsafe { &mut *self.references.add(frame) };
+        if *references == MAX_FRAME_REFERENCES {
+            return Err("[ERR] Frame has too many references");
+        }
+
+        *references += 1;
+        Ok(())
+    }
+
+    /// Number of mappings sharing the frame
+    pub fn references(&self, page: Page) -> usize {
+        let frame = V2P!(page.address as usize) / PAGE_SIZE;
+        unsafe { *self.references.add(frame) as usize }
+    }
+
+    pub fn total_frames(&self) -> usize {
+        self.frames
+    }
+
+    pub fn free_frames(&self) -> usize {
+        self.free
+    }
+
+    pub fn used_frames(&self) -> usize {
+        self.frames - self.free
+    }
+}
+
+unsafe impl Send for FrameAllocator {}
+
+/// Allocate a run of contiguous physical frames. The returned page holds the virtual address
+/// (above KERNEL_BASE) of the first frame.
+pub fn allocate_frames(count: usize) -> Result<Page, &'static str> {
+    FRAME_ALLOCATOR.lock().allocate(count)
+}
+
+/// Release a run of contiguous physical frames
+pub fn deallocate_frames(page: Page, count: usize) {
+    assert_eq!(
+        ROUND_UP!(page.address as usize, PAGE_SIZE),
+        page.address as usize
+    );
+
+    FRAME_ALLOCATOR.lock().deallocate(page, count);
+}
+
+/// Share an allocated frame with one more mapping (see FrameAllocator::share)
+pub fn share_frame(page: Page) -> Result<(), &'static str> {
+    FRAME_ALLOCATOR.lock().share(page)
+}
+
+pub fn frame_references(page: Page) -> usize {
+    FRAME_ALLOCATOR.lock().references(page)
+}
+
+pub fn free_frames() -> usize {
+    FRAME_ALLOCATOR.lock().free_frames()
+}
+
+pub fn used_frames() -> usize {
+    FRAME_ALLOCATOR.lock().used_frames()
+}
+
+/// Build the frame allocator from the physical memory map. Only frames above its metadata are
+/// released, so the Kernel image and the low memory (BIOS data, bootloader structures, etc) are
+/// never handed out.
+pub fn setup_frame_allocator() {
+    let physical_top = unsafe { *PHYSICAL_TOP.lock() };
+    let frames = physical_top / PAGE_SIZE;
+    let bitmap_address = unsafe { ROUND_UP!(&KERNEL_END as *const u8 as usize, PAGE_SIZE) };
+    let first_free = V2P!(bitmap_address) + FrameAllocator::metadata_size(frames);
+
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    unsafe { allocator.init(bitmap_address, frames) };
+
+    for region in PHYSICAL_MEMORY_MAP.lock().usable() {
+        if region.end > first_free {
+            allocator.mark_free(region.start.max(first_free), region.end);
+        }
+    }
+
+    println!(
+        "[KERNEL] Frame Allocator Initialized: {} Free Frames, {} Used Frames",
+        allocator.free_frames(),
+        allocator.used_frames()
+    );
+}
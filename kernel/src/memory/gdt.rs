@@ -1,33 +1,13 @@
 use core::mem::size_of;
 
-use lazy_static::lazy_static;
-use spin::Mutex;
-
 use crate::memory::defs::*;
+use crate::smp::{cpu::CPUS, defs::BOOT_CPU};
 use crate::x86::defs::ShortSegmentDescriptor;
-use crate::x86::helpers::load_cs;
+use crate::x86::helpers::{load_cs, ltr, set_gs};
 use crate::{println, x86::helpers::lgdt};
 
 use super::defs;
 
-lazy_static! {
-    pub static ref TSS: Mutex<TaskStateSegment> = {
-        let mut tss = TaskStateSegment::new();
-        Mutex::new(tss)
-    };
-    static ref GLOBAL_GDT: GlobalDescriptorTable = {
-        let mut gdt = GlobalDescriptorTable::new();
-
-        gdt.add_short_segment(KERNEL_CODE_SEGMENT);
-        gdt.add_short_segment(KERNEL_DATA_SEGMENT);
-        gdt.add_short_segment(USER_CODE_SEGMENT);
-        gdt.add_short_segment(USER_DATA_SEGMENT);
-        gdt.add_short_segment(TSS.lock().get_segment());
-
-        gdt
-    };
-}
-
 /// Global Descriptor Table is used in the process of segmentation. It is responsible
 /// to convert the linear address into virtual address.
 /// See more at https://en.wikipedia.org/wiki/Memory_segmentation
@@ -123,8 +103,9 @@ impl DescriptorFlags {
 }
 
 impl TaskStateSegment {
-    pub fn new() -> Self {
-        TaskStateSegment::default()
+    pub const fn new() -> Self {
+        // Same as the default, but usable in statics
+        unsafe { core::mem::zeroed() }
     }
 
     pub fn get_segment(&self) -> u64 {
@@ -147,10 +128,38 @@ impl TaskStateSegment {
     }
 }
 
-pub fn setup_gdt() {
-    GLOBAL_GDT.refresh();
-    let cs_selector = GLOBAL_GDT.get_selector(1);
-    load_cs(cs_selector);
+/// Place a base address in a segment descriptor
+fn with_base(segment: ShortSegmentDescriptor, base: usize) -> ShortSegmentDescriptor {
+    let base = base as u64;
+    segment | ((base & 0xFF_FFFF) << 16) | ((base >> 24) << 56)
+}
+
+/// Build and load the GDT of the processor at the given index of CPUS. Every processor has its
+/// own, as each one needs its own TSS and per-CPU data segment.
+pub fn load_gdt(id: usize) {
+    let cpu = &CPUS[id];
+    let mut gdt = cpu.gdt.lock();
+
+    *gdt = GlobalDescriptorTable::new();
+    gdt.add_short_segment(KERNEL_CODE_SEGMENT);
+    gdt.add_short_segment(KERNEL_DATA_SEGMENT);
+    gdt.add_short_segment(USER_CODE_SEGMENT);
+    gdt.add_short_segment(USER_DATA_SEGMENT);
+    gdt.add_short_segment(cpu.tss.lock().get_segment());
+    gdt.add_short_segment(with_base(KERNEL_DATA_SEGMENT, cpu as *const _ as usize));
+
+    // The table lives in CPUS, so it stays in place after the lock is released
+    unsafe { lgdt(&gdt.pointer()) };
+    load_cs(gdt.get_selector(KERNEL_CODE_SEG_ENTRY));
+
+    // The TSS only needs to be loaded once, switching processes just updates its fields
+    ltr(TASK_SWITCH_SEG_ENTRY << 3);
+
+    // From now on, this_cpu() finds the entry of this processor
+    set_gs(CPU_SEG_ENTRY << 3);
+}
 
+pub fn setup_gdt() {
+    load_gdt(BOOT_CPU);
     println!("[KERNEL] Global Descriptor Table Initialized ");
 }
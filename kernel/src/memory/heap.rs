@@ -1,63 +1,225 @@
 use alloc::alloc::{GlobalAlloc, Layout};
-use spin::Mutex;
-
 use crate::{
-    memory::mem::MEMORY_REGION, println, structures::static_linked_list::StaticLinkedListNode,
+    memory::vm::map_kernel_pages,
+    println,
+    structures::static_linked_list::StaticLinkedListNode,
+    threading::defs::{SpinLock, SpinLockGuard},
     ROUND_UP,
 };
 
-use super::defs::{LinkedListAllocator, HEAP_PAGES, PAGE_SIZE};
+use super::defs::{
+    HeapAllocator, HeapStats, LinkedListAllocator, PageFlags, HEAP_GROW_PAGES, HEAP_PAGES,
+    HEAP_SIZE_CLASSES, HEAP_SLAB_SIZE, KERNEL_HEAP_END, KERNEL_HEAP_START, PAGE_SIZE,
+};
 
 pub struct Locked<A> {
-    inner: spin::Mutex<A>,
+    inner: SpinLock<A>,
 }
 
 impl<A> Locked<A> {
     pub const fn new(inner: A) -> Self {
         Locked {
-            inner: spin::Mutex::new(inner),
+            inner: SpinLock::new(inner),
         }
     }
 
-    pub fn lock(&self) -> spin::MutexGuard<A> {
+    pub fn lock(&self) -> SpinLockGuard<A> {
         self.inner.lock()
     }
 }
 
 #[global_allocator]
-pub static HEAP_ALLOCATOR: Locked<LinkedListAllocator> = Locked::new(LinkedListAllocator::new());
-pub static IS_HEAP_ENABLED: Mutex<bool> = Mutex::new(false);
+pub static HEAP_ALLOCATOR: Locked<HeapAllocator> = Locked::new(HeapAllocator::new());
+pub static IS_HEAP_ENABLED: SpinLock<bool> = SpinLock::new(false);
+
+const EMPTY_SIZE_CLASS: Option<&'static mut StaticLinkedListNode> = None;
 
 /// Heap Allocator is defined below. Rust no_std environment requires us
 /// to define our own allocator. As such, our goal is to first identify what
 /// memory region is available to be our Heap and then we instruct the allocator
-/// on how to allocate memory in that region. For this implementation, a Linked
-/// List allocator is used.
-unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
+/// on how to allocate memory in that region. Small objects (the vast majority of
+/// kernel allocations) are served from size-class caches, so they never fragment
+/// the heap. Everything else goes through a Linked List allocator that keeps its
+/// free blocks sorted by address and merges neighbours as they are freed. Once
+/// the heap runs out of space, it grows by mapping fresh pages at its end.
+unsafe impl GlobalAlloc for Locked<HeapAllocator> {
     unsafe fn alloc(&self, _layout: Layout) -> *mut u8 {
-        let (size, align) = LinkedListAllocator::size_align(_layout);
         let mut allocator = self.lock();
 
-        // Check if we can allocate a free node. If not, then we ran out of memory
-        if let Some((node, start)) = allocator.search_free_node(size, align) {
-            let end = start.checked_add(size).expect("overflow");
-            let excess_size = node.end_address() - end;
+        let (mut pointer, size) = allocator.allocate(_layout);
 
-            // If there is space left after we take the heap block, then this excess
-            // node should be returned to the linked list
-            if excess_size > 0 {
-                allocator.add_free_node(end, excess_size);
-            }
+        // Out of space: grow the heap by enough to fit the block (whatever its alignment) and
+        // try again. Null is only returned once physical memory is exhausted.
+        if pointer.is_null() && allocator.grow(size + _layout.align().max(PAGE_SIZE)) {
+            pointer = allocator.allocate(_layout).0;
+        }
 
-            start as *mut u8
-        } else {
-            core::ptr::null_mut()
+        match pointer.is_null() {
+            true => allocator.stats.failures += 1,
+            false => {
+                allocator.stats.allocations += 1;
+                allocator.stats.used += size;
+            }
         }
+
+        pointer
     }
 
     unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
-        let (size, _) = LinkedListAllocator::size_align(_layout);
-        self.lock().add_free_node(_ptr as usize, size);
+        let mut allocator = self.lock();
+
+        let size = match HeapAllocator::size_class(_layout) {
+            Some(class) => {
+                allocator.deallocate_small(class, _ptr as usize);
+                HEAP_SIZE_CLASSES[class]
+            }
+            None => {
+                let (size, _) = LinkedListAllocator::size_align(_layout);
+                allocator.free_list.add_free_node(_ptr as usize, size);
+                size
+            }
+        };
+
+        allocator.stats.deallocations += 1;
+        allocator.stats.used -= size;
+    }
+}
+
+impl HeapAllocator {
+    pub const fn new() -> Self {
+        HeapAllocator {
+            top: 0,
+            free_list: LinkedListAllocator::new(),
+            size_classes: [EMPTY_SIZE_CLASS; HEAP_SIZE_CLASSES.len()],
+            stats: HeapStats {
+                size: 0,
+                used: 0,
+                cached: 0,
+                free: 0,
+                free_blocks: 0,
+                largest_free_block: 0,
+                allocations: 0,
+                deallocations: 0,
+                failures: 0,
+            },
+        }
+    }
+
+    /// Set the start of the heap range. The heap is empty until it grows.
+    pub fn init(&mut self, start_address: usize) {
+        self.top = start_address;
+    }
+
+    /// Map at least the given number of bytes at the end of the heap and release them to the
+    /// free list, where they merge with the last free block (if adjacent).
+    ///
+    /// # Safety
+    /// The heap range must have been set with init, and nothing else may map pages in it.
+    pub unsafe fn grow(&mut self, size: usize) -> bool {
+        let pages = (ROUND_UP!(size, PAGE_SIZE) / PAGE_SIZE)
+            .max(HEAP_GROW_PAGES)
+            .min((KERNEL_HEAP_END - self.top) / PAGE_SIZE);
+
+        let mapped = match map_kernel_pages(self.top, pages, PageFlags::WRITABLE) {
+            Ok(()) => pages * PAGE_SIZE,
+            Err(mapped) => mapped,
+        };
+
+        if mapped == 0 {
+            return false;
+        }
+
+        self.free_list.add_free_node(self.top, mapped);
+        self.top += mapped;
+        self.stats.size += mapped;
+
+        mapped >= size
+    }
+
+    /// Allocate a block for the layout, returning it along with the number of bytes it takes
+    unsafe fn allocate(&mut self, layout: Layout) -> (*mut u8, usize) {
+        match HeapAllocator::size_class(layout) {
+            Some(class) => (self.allocate_small(class), HEAP_SIZE_CLASSES[class]),
+            None => {
+                let (size, align) = LinkedListAllocator::size_align(layout);
+                (self.free_list.allocate(size, align), size)
+            }
+        }
+    }
+
+    /// Index of the smallest size class able to hold the layout, if the layout is small enough.
+    /// Blocks of a class are aligned to the class size, so the alignment must fit as well.
+    pub fn size_class(layout: Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align());
+        HEAP_SIZE_CLASSES.iter().position(|&class| size <= class)
+    }
+
+    /// Take a block from the size-class cache, refilling it from the free list when empty
+    unsafe fn allocate_small(&mut self, class: usize) -> *mut u8 {
+        if self.size_classes[class].is_none() && !self.refill_size_class(class) {
+            return core::ptr::null_mut();
+        }
+
+        let block = self.size_classes[class].take().unwrap();
+        self.size_classes[class] = block.next.take();
+        self.stats.cached -= HEAP_SIZE_CLASSES[class];
+
+        block as *mut StaticLinkedListNode as *mut u8
+    }
+
+    /// Return a block to its size-class cache
+    unsafe fn deallocate_small(&mut self, class: usize, address: usize) {
+        let mut block = StaticLinkedListNode::new(HEAP_SIZE_CLASSES[class]);
+        block.next = self.size_classes[class].take();
+
+        let block_address = address as *mut StaticLinkedListNode;
+        block_address.write(block);
+
+        self.size_classes[class] = Some(&mut *block_address);
+        self.stats.cached += HEAP_SIZE_CLASSES[class];
+    }
+
+    /// Carve a slab from the free list and split it into blocks of the class size. Slabs are
+    /// page aligned, so every block is naturally aligned to its size.
+    unsafe fn refill_size_class(&mut self, class: usize) -> bool {
+        let slab = self.free_list.allocate(HEAP_SLAB_SIZE, PAGE_SIZE);
+
+        if slab.is_null() {
+            return false;
+        }
+
+        let class_size = HEAP_SIZE_CLASSES[class];
+        for block in (0..HEAP_SLAB_SIZE / class_size).rev() {
+            self.deallocate_small(class, slab as usize + block * class_size);
+        }
+
+        true
+    }
+
+    /// Snapshot of the heap counters, including a walk of the free list
+    pub fn stats(&self) -> HeapStats {
+        let mut stats = self.stats;
+        let mut node = self.free_list.head.next.as_ref();
+
+        while let Some(current) = node {
+            stats.free += current.size;
+            stats.free_blocks += 1;
+            stats.largest_free_block = stats.largest_free_block.max(current.size);
+            node = current.next.as_ref();
+        }
+
+        stats
+    }
+}
+
+impl HeapStats {
+    /// External fragmentation of the free list, in percent. 0 means every free byte is part of
+    /// a single block, while values close to 100 mean the free memory is scattered in tiny blocks.
+    pub fn fragmentation(&self) -> usize {
+        match self.free {
+            0 => 0,
+            free => 100 - self.largest_free_block * 100 / free,
+        }
     }
 }
 
@@ -70,6 +232,10 @@ impl LinkedListAllocator {
         }
     }
 
+    /// Release the given range to the allocator.
+    ///
+    /// # Safety
+    /// The range must be mapped, writable and unused, and must not be released again.
     pub unsafe fn init(&mut self, start_address: usize, size: usize) {
         self.add_free_node(start_address, size);
     }
@@ -79,6 +245,11 @@ impl LinkedListAllocator {
     /// implemented using Rust's Box, then how do we do it?
     /// The answer is that we don't need the Box just yet, instead, we can use each freed memory block
     /// to store the Linked List Node information. If the block is allocated, Node data can be erased.
+    /// Free nodes are kept sorted by address, so a freed block can be merged with the blocks
+    /// right before and after it. This keeps the heap from fragmenting over time.
+    ///
+    /// # Safety
+    /// The block must be mapped and writable, and must not be used once released.
     pub unsafe fn add_free_node(&mut self, address: usize, size: usize) {
         // Ensures the provided address and size is aligned and capable of holding a list node
         assert_eq!(
@@ -88,15 +259,79 @@ impl LinkedListAllocator {
 
         assert!(size >= core::mem::size_of::<StaticLinkedListNode>());
 
-        // Create a new node to hold the memory region
+        // Find the last node that starts before the freed block
+        let head = &mut self.head as *mut StaticLinkedListNode;
+        let mut previous = head;
+
+        while let Some(node) = (*previous).next.as_mut() {
+            if node.address() > address {
+                break;
+            }
+
+            previous = *node as *mut StaticLinkedListNode;
+        }
+
+        let mut next = (*previous).next.take();
+
+        // Overlapping a free block means the block was freed twice (or never allocated)
+        if (previous != head && (*previous).end_address() > address)
+            || next
+                .as_ref()
+                .is_some_and(|node| address + size > node.address())
+        {
+            panic!("[FATAL] Heap block 0x{:X} freed twice", address);
+        }
+
+        // Create a new node to hold the memory region, absorbing the next node if adjacent
         let mut node = StaticLinkedListNode::new(size);
-        node.next = self.head.next.take(); // When we take, next becomes "None"
+        node.next = match next.take() {
+            Some(next_node) if address + size == next_node.address() => {
+                node.size += next_node.size;
+                next_node.next.take()
+            }
+            next_node => next_node,
+        };
+
+        // If the previous node is adjacent, grow it instead of creating a new node
+        if previous != head && (*previous).end_address() == address {
+            (*previous).size += node.size;
+            (*previous).next = node.next;
+            return;
+        }
 
         // Update the given address with the new node information
         let node_address = address as *mut StaticLinkedListNode;
         node_address.write(node);
 
-        self.head.next = Some(&mut *node_address);
+        (*previous).next = Some(&mut *node_address);
+    }
+
+    /// Take a block of the given size and alignment from the free list. Whatever is left of the
+    /// chosen node, before and after the block, goes back to the list.
+    ///
+    /// # Safety
+    /// Every block in the free list must have been released with init or add_free_node.
+    pub unsafe fn allocate(&mut self, size: usize, align: usize) -> *mut u8 {
+        // Check if we can allocate a free node. If not, then we ran out of memory
+        if let Some((node, start)) = self.search_free_node(size, align) {
+            let node_start = node.address();
+            let node_end = node.end_address();
+            let end = start.checked_add(size).expect("overflow");
+
+            // If there is space left before or after we take the heap block, then this excess
+            // node should be returned to the linked list
+            if start > node_start {
+                self.add_free_node(node_start, start - node_start);
+            }
+
+            if node_end > end {
+                self.add_free_node(end, node_end - end);
+            }
+
+            start as *mut u8
+        } else {
+            core::ptr::null_mut()
+        }
     }
 
     pub fn allocate_free_node(
@@ -113,6 +348,13 @@ impl LinkedListAllocator {
         }
 
         let excess_size = node.end_address() - end;
+        let leading_size = start - node.address();
+
+        // Aligning the start of the block may leave a gap at the beginning of the node. Just
+        // like the excess below, the gap must be able to hold a node of its own.
+        if leading_size > 0 && leading_size < core::mem::size_of::<StaticLinkedListNode>() {
+            return Err(());
+        }
 
         // The allocation procedure splits the region into a allocated region (of "size") and
         // a free region (of node.end - node.start - size). If the newly created free region
@@ -176,12 +418,11 @@ impl LinkedListAllocator {
 pub fn setup_heap() -> Result<(), &'static str> {
     println!("[KERNEL] Setting Up Heap");
 
-    let page_address = MEMORY_REGION.lock().next(HEAP_PAGES)?.address as usize;
+    let mut allocator = HEAP_ALLOCATOR.lock();
+    allocator.init(KERNEL_HEAP_START);
 
-    unsafe {
-        HEAP_ALLOCATOR
-            .lock()
-            .init(page_address, PAGE_SIZE * HEAP_PAGES);
+    if !unsafe { allocator.grow(PAGE_SIZE * HEAP_PAGES) } {
+        return Err("[ERR] Failure to Allocate Heap");
     }
 
     *IS_HEAP_ENABLED.lock() = true;
@@ -190,3 +431,8 @@ pub fn setup_heap() -> Result<(), &'static str> {
 
     Ok(())
 }
+
+/// Read the heap usage and fragmentation counters
+pub fn heap_stats() -> HeapStats {
+    HEAP_ALLOCATOR.lock().stats()
+}
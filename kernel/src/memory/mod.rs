@@ -1,5 +1,9 @@
 pub mod defs;
+pub mod e820;
+pub mod frame;
 pub mod gdt;
 pub mod heap;
 pub mod mem;
+pub mod paging;
+pub mod uaccess;
 pub mod vm;
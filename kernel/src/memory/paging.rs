@@ -0,0 +1,210 @@
+/// Typed view of the x86 two-level paging structures. A virtual address is split into three
+/// parts: the top 10 bits index the page directory, the next 10 bits index the page table, and
+/// the last 12 bits are the offset inside the page. Both levels are arrays of 1024 entries, each
+/// one holding a physical address and a set of PageFlags.
+/// See more at https://wiki.osdev.org/Paging
+use super::defs::*;
+use super::vm::allocate_page;
+use crate::{
+    memory::mem::memset,
+    x86::helpers::{load_cr3, read_cr3},
+    P2V, PAGE_DIR_INDEX, PAGE_TABLE_INDEX, V2P,
+};
+
+impl Page {
+    /// Page holding the given physical address
+    pub fn from_physical(physical_address: usize) -> Self {
+        Page {
+            address: P2V!(physical_address & PAGE_ADDRESS_MASK) as *const usize,
+        }
+    }
+
+    pub fn physical_address(&self) -> usize {
+        V2P!(self.address as usize)
+    }
+}
+
+impl PageTableEntry {
+    pub const fn empty() -> Self {
+        PageTableEntry { entry: 0 }
+    }
+
+    pub fn new(physical_address: usize, flags: PageFlags) -> Self {
+        PageTableEntry {
+            entry: (physical_address & PAGE_ADDRESS_MASK) | flags.bits(),
+        }
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.flags().contains(PageFlags::PRESENT)
+    }
+
+    pub fn flags(&self) -> PageFlags {
+        PageFlags::from_bits_truncate(self.entry & !PAGE_ADDRESS_MASK)
+    }
+
+    /// Physical address of the frame (or page table) this entry points to
+    pub fn physical_address(&self) -> usize {
+        self.entry & PAGE_ADDRESS_MASK
+    }
+
+    /// Frame (or page table) this entry points to
+    pub fn page(&self) -> Page {
+        Page::from_physical(self.physical_address())
+    }
+
+    pub fn set(&mut self, physical_address: usize, flags: PageFlags) {
+        *self = PageTableEntry::new(physical_address, flags);
+    }
+
+    pub fn set_flags(&mut self, flags: PageFlags) {
+        self.entry = self.physical_address() | flags.bits();
+    }
+
+    pub fn clear(&mut self) {
+        self.entry = 0;
+    }
+}
+
+impl PageTable {
+    pub fn zero(&mut self) {
+        memset(self as *mut PageTable as usize, 0, PAGE_SIZE);
+    }
+
+    /// Iterate over the present entries, along with their index
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &PageTableEntry)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.is_present())
+    }
+
+    /// Iterate mutably over the present entries, along with their index
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (usize, &mut PageTableEntry)> {
+        self.entries
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, entry)| entry.is_present())
+    }
+}
+
+impl PageDirectory {
+    /// Allocate an empty page directory
+    pub fn new() -> Result<Self, &'static str> {
+        let directory = PageDirectory::from_page(allocate_page()?);
+        directory.table().zero();
+        Ok(directory)
+    }
+
+    pub fn from_page(page: Page) -> Self {
+        PageDirectory {
+            table: page.address as *mut PageTable,
+        }
+    }
+
+    /// Frame holding the directory itself
+    pub fn page(&self) -> Page {
+        Page {
+            address: self.table as *const usize,
+        }
+    }
+
+    pub fn physical_address(&self) -> usize {
+        V2P!(self.table as usize)
+    }
+
+    /// The directory is a page table whose entries point to page tables
+    pub fn table(&self) -> &'static mut PageTable {
+        unsafe { &mut *self.table }
+    }
+
+    /// Page directory entry covering the given virtual address
+    pub fn entry(&self, virtual_address: usize) -> &'static mut PageTableEntry {
+        &mut self.table().entries[PAGE_DIR_INDEX!(virtual_address)]
+    }
+
+    /// Page table covering the given virtual address, if present
+    pub fn page_table(&self, virtual_address: usize) -> Option<&'static mut PageTable> {
+        let entry = self.entry(virtual_address);
+
+        match entry.is_present() {
+            true => Some(unsafe { &mut *(entry.page().address as *mut PageTable) }),
+            false => None,
+        }
+    }
+
+    /// Index the page directory, and then the page table, returning the page table entry of the
+    /// provided virtual address. If the page table is not present and allocation is allowed,
+    /// allocates a new page to act as the page table.
+    pub fn walk(
+        &self,
+        virtual_address: usize,
+        should_allocate: bool,
+    ) -> Result<&'static mut PageTableEntry, &'static str> {
+        if self.page_table(virtual_address).is_none() {
+            // Since page was not found, we need to allocate
+            if !should_allocate {
+                return Err("Page walk failed: Not allowed to allocate");
+            }
+
+            let page_table = allocate_page()?;
+            memset(page_table.address as usize, 0, PAGE_SIZE);
+
+            self.entry(virtual_address).set(
+                page_table.physical_address(),
+                PageFlags::PRESENT | PageFlags::WRITABLE | PageFlags::USER,
+            );
+        }
+
+        let page_table = self.page_table(virtual_address).unwrap();
+        Ok(&mut page_table.entries[PAGE_TABLE_INDEX!(virtual_address)])
+    }
+
+    /// Page table entry of a mapped virtual address
+    pub fn mapping(&self, virtual_address: usize) -> Option<&'static mut PageTableEntry> {
+        match self.walk(virtual_address, false) {
+            Ok(entry) if entry.is_present() => Some(entry),
+            _ => None,
+        }
+    }
+
+    /// Translate a virtual address into the physical address it is mapped to
+    pub fn translate(&self, virtual_address: usize) -> Option<usize> {
+        let entry = self.mapping(virtual_address)?;
+        Some(entry.physical_address() | (virtual_address & !PAGE_ADDRESS_MASK))
+    }
+
+    /// Iterate over every present user mapping (below KERNEL_BASE), yielding the virtual address
+    /// of the page and its page table entry
+    pub fn user_mappings(&self) -> impl Iterator<Item = (usize, &'static mut PageTableEntry)> {
+        let directory = *self;
+
+        (0..PAGE_DIR_INDEX!(KERNEL_BASE))
+            .filter_map(move |index| {
+                let table = directory.page_table(index * PAGE_TABLE_COVERAGE)?;
+                Some((index, table))
+            })
+            .flat_map(|(index, table)| {
+                table.iter_mut().map(move |(entry_index, entry)| {
+                    (index * PAGE_TABLE_COVERAGE + entry_index * PAGE_SIZE, entry)
+                })
+            })
+    }
+
+    /// Address space currently loaded in CR3
+    pub fn active() -> Self {
+        PageDirectory::from_page(Page::from_physical(read_cr3()))
+    }
+
+    /// Is this the address space currently loaded in CR3?
+    pub fn is_active(&self) -> bool {
+        read_cr3() == self.physical_address()
+    }
+
+    /// Switch to this address space
+    pub fn activate(&self) {
+        load_cr3(self.physical_address());
+    }
+}
+
+unsafe impl Send for PageDirectory {}
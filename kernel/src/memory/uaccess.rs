@@ -0,0 +1,124 @@
+/// Access to the user space from the Kernel. System Calls receive addresses chosen by the user,
+/// which may point anywhere: unmapped memory, Kernel memory or read-only pages. Rather than
+/// dereferencing them, every page is looked up in the page directory of the current process
+/// and checked to be accessible from the user space, and EFAULT is returned otherwise.
+use abi::errno::Errno;
+
+use super::{defs::*, mem::memmove, vm::resolve_copy_on_write};
+use crate::P2V;
+
+/// Kernel (virtual) address where the given user address can be accessed. Pages must be user
+/// pages and, for writes, writable. Copy-on-write pages are copied before being written.
+fn user_address(virtual_address: usize, write: bool) -> Result<usize, Errno> {
+    let page_dir = PageDirectory::active();
+
+    if virtual_address >= USER_STACK_TOP {
+        return Err(Errno::EFAULT);
+    }
+
+    let mut entry = page_dir.mapping(virtual_address).ok_or(Errno::EFAULT)?;
+
+    if !entry.flags().contains(PageFlags::USER) {
+        return Err(Errno::EFAULT);
+    }
+
+    if write && !entry.flags().contains(PageFlags::WRITABLE) {
+        resolve_copy_on_write(page_dir, virtual_address).map_err(|_| Errno::EFAULT)?;
+        entry = page_dir.mapping(virtual_address).ok_or(Errno::EFAULT)?;
+    }
+
+    Ok(P2V!(entry.physical_address()) + (virtual_address & !PAGE_ADDRESS_MASK))
+}
+
+/// Walk a user range page by page, handing the Kernel address and length of each chunk (along
+/// with its offset in the range) to the given function
+fn for_each_user_chunk<F>(address: usize, length: usize, write: bool, mut f: F) -> Result<(), Errno>
+where
+    F: FnMut(usize, usize, usize),
+{
+    address.checked_add(length).ok_or(Errno::EFAULT)?;
+
+    let mut offset = 0;
+    while offset < length {
+        let user_address = address + offset;
+        let chunk = (PAGE_SIZE - (user_address % PAGE_SIZE)).min(length - offset);
+
+        f(self::user_address(user_address, write)?, offset, chunk);
+        offset += chunk;
+    }
+
+    Ok(())
+}
+
+/// Fill the destination buffer with the user memory starting at source
+pub fn copy_from_user(destination: &mut [u8], source: usize) -> Result<(), Errno> {
+    let buffer = destination.as_mut_ptr() as usize;
+
+    for_each_user_chunk(
+        source,
+        destination.len(),
+        false,
+        |address, offset, chunk| unsafe {
+            memmove(address, buffer + offset, chunk);
+        },
+    )
+}
+
+/// Copy the source buffer to the user memory starting at destination
+pub fn copy_to_user(destination: usize, source: &[u8]) -> Result<(), Errno> {
+    let buffer = source.as_ptr() as usize;
+
+    for_each_user_chunk(
+        destination,
+        source.len(),
+        true,
+        |address, offset, chunk| unsafe {
+            memmove(buffer + offset, address, chunk);
+        },
+    )
+}
+
+/// Copy a NUL-terminated string from the user space into the destination buffer, returning its
+/// length (without the NUL). Fails with ENAMETOOLONG if the string (and its NUL) does not fit.
+pub fn strncpy_from_user(destination: &mut [u8], source: usize) -> Result<usize, Errno> {
+    let mut length = 0;
+
+    while length < destination.len() {
+        let user_address = source.checked_add(length).ok_or(Errno::EFAULT)?;
+        let address = self::user_address(user_address, false)?;
+        let chunk = (PAGE_SIZE - (user_address % PAGE_SIZE)).min(destination.len() - length);
+
+        for index in 0..chunk {
+            let byte = unsafe { *((address + index) as *const u8) };
+            destination[length] = byte;
+
+            if byte == 0 {
+                return Ok(length);
+            }
+
+            length += 1;
+        }
+    }
+
+    Err(Errno::ENAMETOOLONG)
+}
+
+/// Read a plain value (an integer, a pointer, etc) from the user space
+pub fn read_from_user<T: Copy + Default>(source: usize) -> Result<T, Errno> {
+    let mut value = T::default();
+    let buffer = unsafe {
+        core::slice::from_raw_parts_mut(&mut value as *mut T as *mut u8, core::mem::size_of::<T>())
+    };
+
+    copy_from_user(buffer, source)?;
+    Ok(value)
+}
+
+/// Write a plain value (an integer, a pointer, etc) to the user space
+pub fn write_to_user<T: Copy>(destination: usize, value: &T) -> Result<(), Errno> {
+    let buffer = unsafe {
+        core::slice::from_raw_parts(value as *const T as *const u8, core::mem::size_of::<T>())
+    };
+
+    copy_to_user(destination, buffer)
+}
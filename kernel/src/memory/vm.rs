@@ -1,14 +1,19 @@
 use lazy_static::lazy_static;
-use spin::Mutex;
 
 use super::{
     defs::*,
-    heap::IS_HEAP_ENABLED,
-    mem::{MEMORY_REGION, PHYSICAL_TOP},
+    e820::PHYSICAL_MEMORY_MAP,
+    frame::{allocate_frames, deallocate_frames, frame_references, share_frame},
+    mem::{memmove, memset, PHYSICAL_TOP},
 };
 use crate::{
-    memory::mem::memset, println, structures::heap_linked_list::HeapLinkedList,
-    x86::helpers::load_cr3, P2V, PAGE_DIR_INDEX, PAGE_TABLE_INDEX, ROUND_DOWN, ROUND_UP, V2P,
+    println,
+    threading::defs::SpinLock,
+    x86::{
+        defs::CR0_WRITE_PROTECT,
+        helpers::{invlpg, load_cr0, read_cr0},
+    },
+    P2V, PAGE_DIR_INDEX, ROUND_DOWN, ROUND_UP, V2P,
 };
 
 extern "C" {
@@ -16,161 +21,456 @@ extern "C" {
 }
 
 lazy_static! {
-    static ref KERNEL_MEMORY_LAYOUT: Mutex<[MemoryLayoutEntry; 4]> = Mutex::new([
-    // I/O Address Space
-    MemoryLayoutEntry {
-        virt: KERNEL_BASE as *const usize,
-        phys_start: 0,
-        phys_end: EXTENDED_MEMORY,
-        perm: PTE_W,
-    },
-    // Kernel Text + Read Only Data
-    MemoryLayoutEntry {
-        virt: KERNEL_LINK as *const usize,
-        phys_start: V2P!(KERNEL_LINK),
-        phys_end: unsafe { V2P!(&KERNEL_DATA as *const u8 as usize) },
-        perm: 0,
-    },
-    // Kernel Data + Memory
-    MemoryLayoutEntry {
-        virt: unsafe { &KERNEL_DATA as *const u8 as *const usize },
-        phys_start: unsafe { V2P!(&KERNEL_DATA as *const u8 as usize) },
-        phys_end: unsafe { *PHYSICAL_TOP.lock() },
-        perm: PTE_W,
-    },
-    // Other Devices
-    MemoryLayoutEntry {
-        virt: DEVICE_SPACE as *const usize,
-        phys_start: DEVICE_SPACE,
-        phys_end: 0,
-        perm: PTE_W,
-    },
-]);
-}
-
-pub static KERNEL_PAGE_DIR: Mutex<Option<usize>> = Mutex::new(None);
-
-lazy_static! {
-    pub static ref FREE_PAGE_LIST: Mutex<HeapLinkedList<Page>> = Mutex::new(HeapLinkedList::new());
+    static ref KERNEL_MEMORY_LAYOUT: SpinLock<MemoryLayout> =
+        SpinLock::new(MemoryLayout::from_memory_map());
 }
 
-/// Perform the allocation of pages. Gives priority to pages in the free list. If there
-/// are no pages in the free list, then allocates from the static memory region. If no
-/// pages are avaialable, raise an exception.
-pub fn allocate_page() -> Result<Page, &'static str> {
-    if *IS_HEAP_ENABLED.lock() == true {
-        let page = FREE_PAGE_LIST.lock().pop();
-
-        match page {
-            Some(page) => return Ok(page),
-            _ => {}
+impl MemoryLayoutEntry {
+    pub const fn empty() -> Self {
+        MemoryLayoutEntry {
+            virt: 0 as *const usize,
+            phys_start: 0,
+            phys_end: 0,
+            perm: PageFlags::empty(),
         }
     }
-
-    return MEMORY_REGION.lock().next(1);
 }
 
-/// Add pages to the Free List. The Free List can only be used if Heap is enabled.
-pub fn deallocate_page(page: Page) {
-    assert_eq!(
-        ROUND_UP!(page.address as usize, 4096),
-        page.address as usize
-    );
-
-    if *IS_HEAP_ENABLED.lock() == false {
-        panic!("[ERROR] Cannot dealocate without heap");
-    }
+impl MemoryLayout {
+    /// Build the Kernel memory layout. The Kernel image is always mapped, and every usable range
+    /// of the physical memory map is mapped right above KERNEL_BASE. Reserved holes are left
+    /// unmapped, except for the ones under EXTENDED_MEMORY, which are mapped as I/O space.
+    pub fn from_memory_map() -> Self {
+        let map = PHYSICAL_MEMORY_MAP.lock();
+        let kernel_data = unsafe { V2P!(&KERNEL_DATA as *const u8 as usize) };
+        let kernel_data_end = map
+            .usable_region(kernel_data)
+            .expect("[FATAL] Kernel is not loaded in usable memory")
+            .end;
+
+        let mut layout = MemoryLayout {
+            entries: [MemoryLayoutEntry::empty(); MAX_LAYOUT_ENTRIES],
+            len: 0,
+        };
+
+        // I/O Address Space
+        layout.add(MemoryLayoutEntry {
+            virt: KERNEL_BASE as *const usize,
+            phys_start: 0,
+            phys_end: EXTENDED_MEMORY,
+            perm: PageFlags::WRITABLE,
+        });
+
+        // Kernel Text + Read Only Data
+        layout.add(MemoryLayoutEntry {
+            virt: KERNEL_LINK as *const usize,
+            phys_start: V2P!(KERNEL_LINK),
+            phys_end: kernel_data,
+            perm: PageFlags::empty(),
+        });
+
+        // Kernel Data + Memory
+        layout.add(MemoryLayoutEntry {
+            virt: P2V!(kernel_data) as *const usize,
+            phys_start: kernel_data,
+            phys_end: kernel_data_end,
+            perm: PageFlags::WRITABLE,
+        });
+
+        // Remaining Usable Memory
+        for region in map
+            .usable()
+            .filter(|region| region.start >= kernel_data_end)
+        {
+            layout.add(MemoryLayoutEntry {
+                virt: P2V!(region.start) as *const usize,
+                phys_start: region.start,
+                phys_end: region.end,
+                perm: PageFlags::WRITABLE,
+            });
+        }
 
-    FREE_PAGE_LIST.lock().push(page);
-}
+        // Other Devices
+        layout.add(MemoryLayoutEntry {
+            virt: DEVICE_SPACE as *const usize,
+            phys_start: DEVICE_SPACE,
+            phys_end: 0,
+            perm: PageFlags::WRITABLE,
+        });
 
-/// Walk Page Directory uses the provided virtual memory address (virtual_address) to index
-/// the page directory, and then the page table. If the page table is not present, allocates
-/// a new page to act as the page table.
-fn walk_page_dir(
-    page_dir: Page,
-    virtual_address: usize,
-    should_allocate: bool,
-) -> Result<*mut usize, &'static str> {
-    let page_directory_offset = PAGE_DIR_INDEX!(virtual_address as isize);
-    let page_directory_entry = unsafe { *page_dir.address.offset(page_directory_offset) as usize };
-    let is_entry_present = (page_directory_entry & PTE_P) > 0;
-
-    let mut page_table: Page = Page {
-        address: 0 as *const usize,
-    };
+        layout
+    }
 
-    // If entry is already present, set page table simply as the address pointed by the entry
-    if is_entry_present == true {
-        page_table.address = P2V!(page_directory_entry & !0xFFF) as *const usize;
-    } else {
-        // Since page was not found, we need to allocate
-        if !should_allocate {
-            return Err("Page walk failed: Not allowed to allocate");
+    fn add(&mut self, entry: MemoryLayoutEntry) {
+        if self.len == self.entries.len() {
+            panic!("[FATAL] Kernel Memory Layout is out of space");
         }
 
-        page_table = allocate_page()?;
-        memset(page_table.address as usize, 0, PAGE_SIZE);
+        self.entries[self.len] = entry;
+        self.len += 1;
+    }
 
-        unsafe {
-            *(page_dir.address.offset(page_directory_offset) as *mut usize) =
-                V2P!(page_table.address as usize) | PTE_P | PTE_W | PTE_U;
-        }
+    pub fn iter(&self) -> impl Iterator<Item = &MemoryLayoutEntry> {
+        self.entries[..self.len].iter()
     }
+}
 
-    let page_table_offset = PAGE_TABLE_INDEX!(virtual_address as isize);
-    let page_table_entry = unsafe { (page_table.address as *mut usize).offset(page_table_offset) };
+pub static KERNEL_PAGE_DIR: SpinLock<Option<PageDirectory>> = SpinLock::new(None);
 
-    // Return the page entry that was found
-    return Ok(page_table_entry);
+/// Allocate a single page from the frame allocator. If no pages are available, raise an
+/// exception.
+pub fn allocate_page() -> Result<Page, &'static str> {
+    allocate_frames(1)
+}
+
+/// Return a single page to the frame allocator. Can be used at any point of the boot process.
+pub fn deallocate_page(page: Page) {
+    deallocate_frames(page, 1);
 }
 
 /// Perform page mapping of a range into the provided page directory.
 /// Creates all the necessary tables to accomodate pages from start_address
 /// to end_address, starting for virtual_address.
 pub fn map_pages(
-    page_dir: Page,
+    page_dir: PageDirectory,
     virtual_address: usize,
     size: usize,
     mut physical_address: usize,
-    perm: usize,
+    perm: PageFlags,
 ) -> Result<(), &'static str> {
-    let mut start_address = ROUND_DOWN!(virtual_address, 4096) as *mut u8;
-    let end_address =
-        ROUND_DOWN!(virtual_address.wrapping_add(size).wrapping_sub(1), 4096) as *mut u8;
+    let mut address = ROUND_DOWN!(virtual_address, PAGE_SIZE);
+    let end_address = ROUND_DOWN!(
+        virtual_address.wrapping_add(size).wrapping_sub(1),
+        PAGE_SIZE
+    );
 
     loop {
-        let page_table_entry = walk_page_dir(page_dir, start_address as usize, true)?;
-        let is_page_entry_present = unsafe { *page_table_entry & PTE_P } > 0;
+        let page_table_entry = page_dir.walk(address, true)?;
 
         // If the page is already mapped, then something went wrong
-        if is_page_entry_present {
+        if page_table_entry.is_present() {
             return Err("[FATAL] Page was remapped");
         }
 
         // Map the page entry to the physical address
-        unsafe { *page_table_entry = physical_address | perm | PTE_P }
+        page_table_entry.set(physical_address, perm | PageFlags::PRESENT);
 
-        if start_address >= end_address {
+        if address >= end_address {
             break;
         }
 
-        start_address = unsafe { start_address.offset(PAGE_SIZE as isize) };
+        address += PAGE_SIZE;
         physical_address += PAGE_SIZE;
     }
 
     return Ok(());
 }
 
+/// Remove the mappings of a range from the provided page directory. Pages that are not mapped
+/// are skipped. If free_frames is set, the physical frames backing the range are returned to
+/// the frame allocator. Stale TLB entries are invalidated with invlpg. Other processors need
+/// no invalidation: the Kernel half is never unmapped, and a user page directory is only active
+/// on the processor running its process, which reloads CR3 (flushing its TLB) on every switch.
+pub fn unmap_pages(
+    page_dir: PageDirectory,
+    virtual_address: usize,
+    size: usize,
+    free_frames: bool,
+) -> Result<(), &'static str> {
+    if size == 0 {
+        return Ok(());
+    }
+
+    let mut address = ROUND_DOWN!(virtual_address, PAGE_SIZE);
+    let end_address = ROUND_DOWN!(
+        virtual_address.wrapping_add(size).wrapping_sub(1),
+        PAGE_SIZE
+    );
+
+    loop {
+        match page_dir.walk(address, false) {
+            Ok(page_table_entry) => {
+                if page_table_entry.is_present() {
+                    if free_frames {
+                        deallocate_page(page_table_entry.page());
+                    }
+
+                    page_table_entry.clear();
+                    invlpg(address);
+                }
+            }
+            // Page table not present: nothing is mapped until the next page table
+            Err(_) => {
+                address =
+                    ROUND_DOWN!(address, PAGE_TABLE_COVERAGE) + PAGE_TABLE_COVERAGE - PAGE_SIZE
+            }
+        }
+
+        if address >= end_address {
+            break;
+        }
+
+        address += PAGE_SIZE;
+    }
+
+    return Ok(());
+}
+
+/// Allocate zeroed frames for every page of the range between start_address and end_address
+/// and map them into the provided page directory. If a frame cannot be allocated or mapped,
+/// the pages mapped so far are released before returning.
+pub fn allocate_user_pages(
+    page_dir: PageDirectory,
+    start_address: usize,
+    end_address: usize,
+    perm: PageFlags,
+) -> Result<(), &'static str> {
+    let start_address = ROUND_DOWN!(start_address, PAGE_SIZE);
+    let end_address = ROUND_UP!(end_address, PAGE_SIZE);
+
+    if end_address > KERNEL_BASE || start_address > end_address {
+        return Err("[ERR] User range overlaps Kernel memory");
+    }
+
+    for address in (start_address..end_address).step_by(PAGE_SIZE) {
+        let result = allocate_page().and_then(|page| {
+            memset(page.address as usize, 0, PAGE_SIZE);
+
+            map_pages(page_dir, address, PAGE_SIZE, page.physical_address(), perm).map_err(|err| {
+                deallocate_page(page);
+                err
+            })
+        });
+
+        if let Err(err) = result {
+            unmap_pages(page_dir, start_address, address - start_address, true)?;
+            return Err(err);
+        }
+    }
+
+    return Ok(());
+}
+
+/// Translate a virtual address of a page mapped in the user space
+fn translate_user(page_dir: PageDirectory, virtual_address: usize) -> Option<usize> {
+    if virtual_address >= KERNEL_BASE {
+        return None;
+    }
+
+    match page_dir.mapping(virtual_address) {
+        Some(entry) if entry.flags().contains(PageFlags::USER) => {
+            Some(entry.physical_address() | (virtual_address & !PAGE_ADDRESS_MASK))
+        }
+        _ => None,
+    }
+}
+
+/// Copy a buffer into the address space described by the provided page directory. Since the
+/// page directory may not be the active one, the copy is done page by page through the Kernel
+/// mapping of each frame. Every destination page must already be mapped.
+pub unsafe fn copy_to_page_dir(
+    page_dir: PageDirectory,
+    virtual_address: usize,
+    source: *const u8,
+    length: usize,
+) -> Result<(), &'static str> {
+    let mut copied = 0;
+
+    while copied < length {
+        let address = virtual_address + copied;
+        let physical_address =
+            translate_user(page_dir, address).ok_or("[ERR] Copy to unmapped user page")?;
+        let chunk = (PAGE_SIZE - (address % PAGE_SIZE)).min(length - copied);
+
+        memmove(source.add(copied) as usize, P2V!(physical_address), chunk);
+        copied += chunk;
+    }
+
+    return Ok(());
+}
+
+/// Fill a range of the address space described by the provided page directory with the given
+/// value. Like copy_to_page_dir, every page of the range must already be mapped.
+pub fn fill_page_dir(
+    page_dir: PageDirectory,
+    virtual_address: usize,
+    value: u8,
+    length: usize,
+) -> Result<(), &'static str> {
+    let mut filled = 0;
+
+    while filled < length {
+        let address = virtual_address + filled;
+        let physical_address =
+            translate_user(page_dir, address).ok_or("[ERR] Fill of unmapped user page")?;
+        let chunk = (PAGE_SIZE - (address % PAGE_SIZE)).min(length - filled);
+
+        memset(P2V!(physical_address), value, chunk);
+        filled += chunk;
+    }
+
+    return Ok(());
+}
+
+/// Release every user mapping (below KERNEL_BASE) of a page directory, along with the frames
+/// backing them, the user page tables and the page directory itself. The Kernel half of the
+/// directory is shared with every other address space (see create_page_dir), so it is left
+/// untouched.
+pub fn free_page_directory(page_dir: PageDirectory) {
+    // Never free the address space we are running on
+    if page_dir.is_active() {
+        kernel_page_dir().activate();
+    }
+
+    for (_, entry) in page_dir.user_mappings() {
+        deallocate_page(entry.page());
+    }
+
+    for index in 0..PAGE_DIR_INDEX!(KERNEL_BASE) {
+        let entry = &mut page_dir.table().entries[index];
+
+        if entry.is_present() {
+            deallocate_page(entry.page());
+            entry.clear();
+        }
+    }
+
+    deallocate_page(page_dir.page());
+}
+
+/// Create a new page directory holding the Kernel mappings and a copy of every user mapping of
+/// the provided page directory. Rather than copying the user pages, their frames are shared by
+/// both directories: writable pages become read-only and copy-on-write in both, so the first
+/// write to one of them gets a private copy (see resolve_copy_on_write). Frames that cannot be
+/// shared anymore are copied right away.
+pub fn copy_page_directory(page_dir: PageDirectory) -> Result<PageDirectory, &'static str> {
+    let new_page_dir = create_page_dir()?;
+
+    for (address, entry) in page_dir.user_mappings() {
+        let mut perm = entry.flags() - PageFlags::ACCESSED - PageFlags::DIRTY;
+
+        if perm.contains(PageFlags::WRITABLE) {
+            perm = (perm - PageFlags::WRITABLE) | PageFlags::COPY_ON_WRITE;
+        }
+
+        let result = match share_frame(entry.page()) {
+            Ok(()) => {
+                entry.set_flags((entry.flags() - PageFlags::WRITABLE) | perm);
+                map_pages(
+                    new_page_dir,
+                    address,
+                    PAGE_SIZE,
+                    entry.physical_address(),
+                    perm,
+                )
+                .map_err(|err| {
+                    deallocate_page(entry.page());
+                    err
+                })
+            }
+            Err(_) => copy_page(new_page_dir, address, entry),
+        };
+
+        if let Err(err) = result {
+            free_page_directory(new_page_dir);
+            return Err(err);
+        }
+    }
+
+    // Pages of the original directory may have become read-only
+    if page_dir.is_active() {
+        page_dir.activate();
+    }
+
+    return Ok(new_page_dir);
+}
+
+/// Map a private copy of the page described by the provided entry into the page directory
+fn copy_page(
+    page_dir: PageDirectory,
+    virtual_address: usize,
+    entry: &PageTableEntry,
+) -> Result<(), &'static str> {
+    let page = allocate_page()?;
+    let mut perm = entry.flags() - PageFlags::ACCESSED - PageFlags::DIRTY;
+
+    if perm.contains(PageFlags::COPY_ON_WRITE) {
+        perm = (perm - PageFlags::COPY_ON_WRITE) | PageFlags::WRITABLE;
+    }
+
+    unsafe {
+        memmove(
+            entry.page().address as usize,
+            page.address as usize,
+            PAGE_SIZE,
+        )
+    };
+
+    map_pages(
+        page_dir,
+        virtual_address,
+        PAGE_SIZE,
+        page.physical_address(),
+        perm,
+    )
+    .map_err(|err| {
+        deallocate_page(page);
+        err
+    })
+}
+
+/// Resolve a write to a copy-on-write page. If the frame is still shared, the page gets a private
+/// copy of it. Otherwise, the page is the last one using the frame and can simply become
+/// writable again. Fails if the address is not mapped as copy-on-write.
+pub fn resolve_copy_on_write(
+    page_dir: PageDirectory,
+    virtual_address: usize,
+) -> Result<(), &'static str> {
+    if virtual_address >= KERNEL_BASE {
+        return Err("[ERR] Copy-on-write of Kernel memory");
+    }
+
+    let entry = page_dir
+        .mapping(virtual_address)
+        .ok_or("[ERR] Copy-on-write of unmapped page")?;
+    let flags = entry.flags();
+
+    if !flags.contains(PageFlags::COPY_ON_WRITE) {
+        return Err("[ERR] Page is not copy-on-write");
+    }
+
+    let writable = (flags - PageFlags::COPY_ON_WRITE) | PageFlags::WRITABLE;
+
+    if frame_references(entry.page()) > 1 {
+        let page = allocate_page()?;
+
+        unsafe {
+            memmove(
+                entry.page().address as usize,
+                page.address as usize,
+                PAGE_SIZE,
+            )
+        };
+        deallocate_page(entry.page());
+        entry.set(page.physical_address(), writable);
+    } else {
+        entry.set_flags(writable);
+    }
+
+    invlpg(virtual_address);
+
+    return Ok(());
+}
+
 /// Maps each one of the entries of KERNEL_MEMORY_LAYOUT into a new page directory,
-/// later switching CR3 to this new page directory.
-pub fn setup_kernel_page_tables() -> Result<Page, &'static str> {
-    let page_dir: Page = allocate_page()?;
+/// later switching CR3 to this new page directory. The page tables covering the heap range
+/// are created upfront, so that every page directory sharing the Kernel mappings also sees
+/// the heap as it grows.
+pub fn setup_kernel_page_tables() -> Result<PageDirectory, &'static str> {
+    let page_dir = PageDirectory::new()?;
     let physical_top = unsafe { *PHYSICAL_TOP.lock() };
 
-    memset(page_dir.address as usize, 0, PAGE_SIZE);
-
-    if P2V!(physical_top) > DEVICE_SPACE {
+    if P2V!(physical_top) > KERNEL_HEAP_START {
         panic!("PHYSTOP is too high");
     }
 
@@ -184,15 +484,64 @@ pub fn setup_kernel_page_tables() -> Result<Page, &'static str> {
         )?;
     }
 
-    let mut kernel_page_dir = KERNEL_PAGE_DIR.lock();
-    *kernel_page_dir = Some(page_dir.address as usize);
+    for address in (KERNEL_HEAP_START..KERNEL_HEAP_END).step_by(PAGE_TABLE_COVERAGE) {
+        page_dir.walk(address, true)?;
+    }
+
+    *KERNEL_PAGE_DIR.lock() = Some(page_dir);
 
     // Switch to new page directory
-    load_cr3(V2P!(kernel_page_dir.unwrap()));
+    page_dir.activate();
+
+    return Ok(page_dir);
+}
+
+/// Page directory built by setup_kernel_page_tables, holding only the Kernel mappings
+pub fn kernel_page_dir() -> PageDirectory {
+    KERNEL_PAGE_DIR
+        .lock()
+        .expect("[FATAL] Kernel page directory is not set up")
+}
+
+/// Create a new page directory holding the Kernel mappings. Rather than mapping the Kernel
+/// memory layout again, the Kernel half of the directory points to the very same page tables
+/// used by KERNEL_PAGE_DIR, so changes to Kernel mappings (such as the heap growing) are seen
+/// by every address space.
+pub fn create_page_dir() -> Result<PageDirectory, &'static str> {
+    let kernel_entries = &kernel_page_dir().table().entries;
+    let page_dir = PageDirectory::new()?;
+
+    for index in PAGE_DIR_INDEX!(KERNEL_BASE)..PAGE_TABLE_ENTRIES {
+        page_dir.table().entries[index] = kernel_entries[index];
+    }
 
     return Ok(page_dir);
 }
 
+/// Map fresh frames (with the given permissions) into the Kernel page directory. Used by the
+/// heap to grow into its reserved virtual range. On failure, pages mapped so far are kept and
+/// the number of bytes mapped is returned as the error.
+pub fn map_kernel_pages(
+    virtual_address: usize,
+    pages: usize,
+    perm: PageFlags,
+) -> Result<(), usize> {
+    let kernel_page_dir = kernel_page_dir();
+
+    for index in 0..pages {
+        let page = allocate_page().map_err(|_| index * PAGE_SIZE)?;
+        let address = virtual_address + index * PAGE_SIZE;
+        let physical_address = page.physical_address();
+
+        if map_pages(kernel_page_dir, address, PAGE_SIZE, physical_address, perm).is_err() {
+            deallocate_page(page);
+            return Err(index * PAGE_SIZE);
+        }
+    }
+
+    Ok(())
+}
+
 /// When the Kernel starts, it has only two pages defined. One page maps the physical
 /// address and the other maps all addresses above KERNEL_BASE to the physical memory.
 /// Here, we need to map the Kernel's memory layout to the one defined in KERNEL_MEMORY_LAYOUT,
@@ -200,6 +549,9 @@ pub fn setup_kernel_page_tables() -> Result<Page, &'static str> {
 pub fn setup_vm() {
     println!("[KERNEL] Mapping Memory");
     setup_kernel_page_tables().expect("[ERR] Failed to Setup Virtual Memory");
+
+    // Copy-on-write pages must also fault when written by the Kernel
+    load_cr0(read_cr0() | CR0_WRITE_PROTECT);
     println!("[KERNEL] Virtual Memory Initialized");
 }
 
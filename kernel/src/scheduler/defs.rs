@@ -1,11 +1,21 @@
 pub mod process {
     use alloc::string::String;
 
-    #[derive(Debug, Copy, Clone)]
+    use crate::memory::defs::PageDirectory;
+
+    pub const INIT_PATH: &str = "/init";
+    pub const INIT_PID: usize = 0; // Adopts the children of exiting processes
+    pub const KILLED_EXIT_STATUS: usize = usize::MAX; // Exit status (-1) of killed processes
+    pub const MAX_ARGS: usize = 32; // Maximum number of arguments passed to exec
+    pub const MAX_ARG_LENGTH: usize = 256; // Maximum length of a path or argument (with its NUL)
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub enum ProcessState {
         EMBRYO,
         RUNNING,
         READY,
+        SLEEPING,
+        ZOMBIE,
         STOPPED,
         KILLED,
     }
@@ -56,8 +66,11 @@ pub mod process {
     #[derive(Debug)]
     pub struct Process {
         pub pid: usize,
-        pub pgdir: Option<*mut usize>,
+        pub parent: Option<usize>, // PID of the parent process
+        pub exit_status: usize,
+        pub pgdir: Option<PageDirectory>,
         pub state: ProcessState,
+        pub channel: Option<usize>, // What the process is sleeping on
         pub context: Option<*mut Context>,
         pub trapframe: Option<*mut TrapFrame>,
         pub kernel_stack: Option<*mut usize>,
@@ -65,13 +78,24 @@ pub mod process {
         pub current_working_directory: String,
         pub name: String,
     }
+
+    // Processes move between the process list and the scheduler of whichever processor runs
+    // them. The raw pointers all point into the kernel stack page owned by the process, and the
+    // page directory is only ever active on the processor running it, so whoever holds the
+    // Process (under the process list or a scheduler lock) is the only one touching them.
+    unsafe impl Send for Process {}
 }
 
 pub mod scheduler {
     use super::process::Process;
 
+    /// Number of timer ticks a process runs for before being preempted, until changed with
+    /// set_time_slice
+    pub const DEFAULT_TIME_SLICE: usize = 10;
+
     pub struct Scheduler {
         pub current_process: Option<Process>,
         pub context: usize,
+        pub ticks: usize, // Ticks used by the current process in its time slice
     }
 }
@@ -1,32 +1,40 @@
+use abi::errno::Errno;
 use alloc::string::String;
-use spin::Mutex;
+use core::{
+    mem::size_of,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use super::{
-    defs::process::{Context, Process, ProcessState, TrapFrame},
-    scheduler::PROCESS_LIST,
+    defs::process::{Context, Process, ProcessState, TrapFrame, INIT_PATH, INIT_PID, MAX_ARGS},
+    scheduler::{cpu_scheduler, sched, sleep, PROCESS_LIST},
 };
 use crate::{
-    interrupts::defs::InterruptStackFrame,
+    elf::{defs::ElfImage, loader::load_elf},
+    fs::embedded::lookup,
     memory::{
         defs::{
-            Page, KERNEL_BASE, KERNEL_DATA_SEG_ENTRY, PAGE_SIZE, PTE_U, PTE_W,
-            TASK_SWITCH_SEG_ENTRY, USER_CODE_SEG_ENTRY, USER_DATA_SEG_ENTRY,
+            Page, PageDirectory, PageFlags, KERNEL_DATA_SEG_ENTRY, PAGE_SIZE, USER_CODE_SEG_ENTRY,
+            USER_DATA_SEG_ENTRY, USER_STACK_PAGES, USER_STACK_TOP,
+        },
+        mem::memset,
+        vm::{
+            allocate_page, allocate_user_pages, copy_page_directory, copy_to_page_dir,
+            create_page_dir, deallocate_page, fill_page_dir, free_page_directory,
         },
-        gdt::TSS,
-        mem::{memmove, memset},
-        vm::{allocate_page, map_pages, setup_kernel_page_tables},
-    },
-    x86::{
-        defs::PrivilegeLevel,
-        helpers::{load_cr3, ltr},
     },
-    V2P,
+    smp::cpu::{started_cpus, this_cpu},
+    x86::defs::{PrivilegeLevel, EFLAGS_INTERRUPT_ENABLE},
+    ROUND_DOWN,
 };
 
 impl Process {
     pub fn new(pid: usize) -> Self {
         Process {
             state: ProcessState::EMBRYO,
+            channel: None,
+            parent: None,
+            exit_status: 0,
             mem_size: Default::default(),
             current_working_directory: String::from("/"),
             name: String::from(""),
@@ -40,25 +48,21 @@ impl Process {
 }
 
 extern "C" {
-    static _binary_init_start: usize;
-    static _binary_init_size: usize;
-
     pub fn trap_return();
-    pub fn trap_enter(frame: InterruptStackFrame);
 }
 
-static mut NEXT_PID: Mutex<usize> = Mutex::new(0);
+static NEXT_PID: AtomicUsize = AtomicUsize::new(0);
 
 /// Add a process to the scheduler queue list.
 pub unsafe fn queue_process(process: Process) {
-    PROCESS_LIST.lock().push(process);
+    PROCESS_LIST.lock().push_back(process);
 }
 
 /// Spawn a process block. Notice the process block has no meaning until it is queued to be run
 /// by the Scheduler. At this point, it is generally prepared to run in the user-space, but no
 /// specific are provided.
 pub unsafe fn spawn_process() -> Result<Process, &'static str> {
-    let pid = *NEXT_PID.lock();
+    let pid = NEXT_PID.fetch_add(1, Ordering::SeqCst);
     let mut process = Process::new(pid);
     let trapframe_size = core::mem::size_of::<TrapFrame>() as isize;
     let context_size = core::mem::size_of::<Context>() as isize;
@@ -66,7 +70,6 @@ pub unsafe fn spawn_process() -> Result<Process, &'static str> {
     let mut esp = kernel_page.offset(PAGE_SIZE as isize / 4);
 
     process.kernel_stack = Some(kernel_page);
-    process.mem_size = PAGE_SIZE;
 
     // Setup Trapframe Layout
     esp = esp.offset(-trapframe_size / 4);
@@ -81,7 +84,6 @@ pub unsafe fn spawn_process() -> Result<Process, &'static str> {
     // Create Trap Return
     (*process.context.unwrap()).eip = trap_return as *const () as usize;
 
-    *NEXT_PID.lock() += 1;
     Ok(process)
 }
 
@@ -92,64 +94,342 @@ pub unsafe fn spawn_process() -> Result<Process, &'static str> {
 pub unsafe fn switch_user_virtual_memory(process: &Process) {
     let page_dir = process
         .pgdir
-        .expect("[FATAL] Process has no page directory") as usize;
+        .expect("[FATAL] Process has no page directory");
 
-    let mut tss = TSS.lock();
-    (*tss).esp0 = process.kernel_stack.unwrap().offset(PAGE_SIZE as isize) as u32;
+    let mut tss = this_cpu().tss.lock();
+    (*tss).esp0 = process.kernel_stack.unwrap().offset(PAGE_SIZE as isize / 4) as u32;
     (*tss).ss0 = (KERNEL_DATA_SEG_ENTRY << 3) as u16;
 
-    ltr(TASK_SWITCH_SEG_ENTRY << 3);
-    load_cr3(V2P!(page_dir));
+    page_dir.activate();
+}
+
+/// Build the user half of an address space: the segments of the ELF executable are loaded at
+/// the addresses they were linked to, and a separate stack of USER_STACK_PAGES pages is mapped
+/// right below USER_STACK_TOP. Returns the loaded executable.
+pub unsafe fn setup_user_virtual_memory(
+    page_dir: PageDirectory,
+    address: *const u8,
+    size: usize,
+) -> Result<ElfImage, Errno> {
+    let stack_bottom = USER_STACK_TOP - USER_STACK_PAGES * PAGE_SIZE;
+    let flags = PageFlags::WRITABLE | PageFlags::USER;
+
+    // Prepare all pages required by the process and copy over the executable binary and data
+    let image = load_elf(page_dir, address, size)?;
+
+    // Stack lives at the top of user space, far from the image
+    allocate_user_pages(page_dir, stack_bottom, USER_STACK_TOP, flags)
+        .map_err(|_| Errno::ENOMEM)?;
+
+    Ok(image)
 }
 
-/// Migrate from Kernel Virtual Memory to User Virtual Memory. Notice that
-pub unsafe fn setup_user_virtual_memory(page_dir: Page, address: *const usize, size: usize) {
-    if size >= PAGE_SIZE {
-        panic!("[FATAL] User Virtual Memory is bigger than one page");
+/// Lay out the arguments of a program at the top of its user stack, as its entry point expects
+/// them: the strings themselves, the NULL-terminated argv array pointing to them, and finally
+/// argv, argc and a fake return address. Returns the initial stack pointer, or E2BIG if the
+/// arguments do not fit.
+pub unsafe fn setup_user_stack(page_dir: PageDirectory, argv: &[String]) -> Result<usize, Errno> {
+    let stack_bottom = USER_STACK_TOP - USER_STACK_PAGES * PAGE_SIZE;
+    let mut pointers = [0usize; MAX_ARGS + 1];
+    let mut esp = USER_STACK_TOP;
+
+    if argv.len() > MAX_ARGS {
+        return Err(Errno::E2BIG);
     }
 
-    let memory_page = allocate_page().expect("[FATAL] Failed to Allocate");
-    let virtual_address = 0;
-    let page_size = PAGE_SIZE;
-    let phys_address = V2P!(memory_page.address as usize);
-    let flags = PTE_W | PTE_U;
+    for (index, argument) in argv.iter().enumerate() {
+        esp = ROUND_DOWN!(esp - (argument.len() + 1), 4);
 
-    // Prepare all pages required by the process and copy over the executable binary and data
-    memset(memory_page.address as usize, 0, PAGE_SIZE);
-    map_pages(page_dir, virtual_address, page_size, phys_address, flags);
-    memmove(address as usize, memory_page.address as usize, size);
+        if esp < stack_bottom {
+            return Err(Errno::E2BIG);
+        }
+
+        copy_to_page_dir(page_dir, esp, argument.as_ptr(), argument.len())
+            .map_err(|_| Errno::EFAULT)?;
+        fill_page_dir(page_dir, esp + argument.len(), 0, 1).map_err(|_| Errno::EFAULT)?;
+        pointers[index] = esp;
+    }
+
+    let pointers = &pointers[..argv.len() + 1];
+    let argv_address = esp - pointers.len() * size_of::<usize>();
+    let header = [0xFFFFFFFF, argv.len(), argv_address]; // Return Address, argc, argv
+    esp = argv_address - header.len() * size_of::<usize>();
+
+    if esp < stack_bottom {
+        return Err(Errno::E2BIG);
+    }
+
+    copy_to_page_dir(
+        page_dir,
+        argv_address,
+        pointers.as_ptr() as *const u8,
+        pointers.len() * size_of::<usize>(),
+    )
+    .map_err(|_| Errno::EFAULT)?;
+    copy_to_page_dir(
+        page_dir,
+        esp,
+        header.as_ptr() as *const u8,
+        header.len() * size_of::<usize>(),
+    )
+    .map_err(|_| Errno::EFAULT)?;
+
+    Ok(esp)
+}
+
+/// Build a complete user address space running the executable at the given path with the
+/// given arguments. Returns the new page directory, the loaded executable and the initial
+/// stack pointer. Nothing is left allocated on failure.
+pub unsafe fn load_program(
+    path: &str,
+    argv: &[String],
+) -> Result<(PageDirectory, ElfImage, usize), Errno> {
+    let file = lookup(path).ok_or(Errno::ENOENT)?;
+    let page_dir = create_page_dir().map_err(|_| Errno::ENOMEM)?;
+
+    let result = setup_user_virtual_memory(page_dir, file.as_ptr(), file.len()).and_then(|image| {
+        let esp = setup_user_stack(page_dir, argv)?;
+        Ok((page_dir, image, esp))
+    });
+
+    if result.is_err() {
+        free_page_directory(page_dir);
+    }
+
+    result
+}
+
+/// Name of a process running the executable at the given path (its last component)
+fn program_name(path: &str) -> String {
+    String::from(path.rsplit('/').next().unwrap_or(path))
 }
 
 /// Spawns the first process to run in the user space, the init process. Subsequent children inherit
 /// many attributes of the init process, such as trapframe,
 pub unsafe fn spawn_init_process() -> Result<(), &'static str> {
     let mut process = spawn_process()?;
-    let kernel_pgdir = setup_kernel_page_tables()?;
     let user_code_selector = (USER_CODE_SEG_ENTRY << 3) as u16 | PrivilegeLevel::Ring3 as u16;
     let user_data_selector = (USER_DATA_SEG_ENTRY << 3) as u16 | PrivilegeLevel::Ring3 as u16;
 
-    process.pgdir = Some(kernel_pgdir.address as *mut usize);
+    let argv = [program_name(INIT_PATH)];
+    let (page_dir, image, esp) = match load_program(INIT_PATH, &argv) {
+        Ok(program) => program,
+        Err(_) => {
+            deallocate_page(Page {
+                address: process.kernel_stack.unwrap(),
+            });
+            return Err("[ERR] Failed to load the init executable");
+        }
+    };
 
-    setup_user_virtual_memory(
-        kernel_pgdir,
-        &_binary_init_start as *const usize,
-        &_binary_init_size as *const usize as usize,
-    );
+    process.pgdir = Some(page_dir);
+    process.mem_size = image.end;
 
     // Setup Trapframe
-    (*process.trapframe.unwrap()).esp = PAGE_SIZE;
-    (*process.trapframe.unwrap()).eip = 0;
+    (*process.trapframe.unwrap()).esp = esp;
+    (*process.trapframe.unwrap()).eip = image.entry;
     (*process.trapframe.unwrap()).cs = user_code_selector;
     (*process.trapframe.unwrap()).ds = user_data_selector;
     (*process.trapframe.unwrap()).es = user_data_selector;
     (*process.trapframe.unwrap()).ss = user_data_selector;
-    (*process.trapframe.unwrap()).eflags = 0x0;
+    (*process.trapframe.unwrap()).eflags = EFLAGS_INTERRUPT_ENABLE;
 
     // Setup Misc
-    process.name = String::from("init");
+    process.name = program_name(INIT_PATH);
     process.state = ProcessState::READY;
 
     queue_process(process);
 
     Ok(())
 }
+
+/// Replace the image of the current process with the executable at the given path. The new
+/// address space is completely built before touching the process, so on failure the process
+/// is left untouched. Once exec returns to the user space, the new program starts running.
+pub unsafe fn exec(path: &str, argv: &[String]) -> Result<(), Errno> {
+    let (page_dir, image, esp) = load_program(path, argv)?;
+
+    let old_page_dir = {
+        let mut scheduler = cpu_scheduler().lock();
+        let process = match scheduler.current_process.as_mut() {
+            Some(process) => process,
+            None => {
+                drop(scheduler);
+                free_page_directory(page_dir);
+                return Err(Errno::ESRCH);
+            }
+        };
+
+        let trapframe = process.trapframe.expect("[FATAL] Process has no trapframe");
+        (*trapframe).eip = image.entry;
+        (*trapframe).esp = esp;
+
+        process.name = program_name(path);
+        process.mem_size = image.end;
+        process.pgdir.replace(page_dir)
+    };
+
+    // Only drop the old address space once the new one is in use
+    page_dir.activate();
+
+    if let Some(old_page_dir) = old_page_dir {
+        free_page_directory(old_page_dir);
+    }
+
+    Ok(())
+}
+
+/// Create a copy of the current process: its user memory, trapframe and working directory are
+/// duplicated, so the child resumes from the same point as the parent. Notice the child sees 0
+/// as the return value of the system call. Returns the PID of the child.
+pub unsafe fn fork() -> Result<usize, &'static str> {
+    let (parent_pid, page_dir, trapframe, mem_size, current_working_directory, name) = {
+        let scheduler = cpu_scheduler().lock();
+        let parent = scheduler
+            .current_process
+            .as_ref()
+            .ok_or("[ERR] No process to fork")?;
+
+        (
+            parent.pid,
+            parent.pgdir.expect("[FATAL] Process has no page directory"),
+            *parent.trapframe.expect("[FATAL] Process has no trapframe"),
+            parent.mem_size,
+            parent.current_working_directory.clone(),
+            parent.name.clone(),
+        )
+    };
+
+    let mut child = spawn_process()?;
+    let kernel_stack = child.kernel_stack.unwrap();
+
+    child.pgdir = match copy_page_directory(page_dir) {
+        Ok(child_page_dir) => Some(child_page_dir),
+        Err(err) => {
+            deallocate_page(Page {
+                address: kernel_stack,
+            });
+            return Err(err);
+        }
+    };
+
+    *child.trapframe.unwrap() = trapframe;
+    (*child.trapframe.unwrap()).eax = 0;
+
+    child.parent = Some(parent_pid);
+    child.mem_size = mem_size;
+    child.current_working_directory = current_working_directory;
+    child.name = name;
+    child.state = ProcessState::READY;
+
+    let pid = child.pid;
+    queue_process(child);
+
+    Ok(pid)
+}
+
+/// Channel parents sleep on while waiting for their children to exit. The scheduler wakes them
+/// up once the zombie child is in the process list.
+pub fn exit_channel() -> usize {
+    &EXIT_CHANNEL as *const u8 as usize
+}
+
+static EXIT_CHANNEL: u8 = 0;
+
+/// Terminate the current process. Its memory is released right away, but the process remains
+/// as a zombie holding its exit status until its parent collects it with wait. Its kernel stack
+/// is released by the scheduler, once the process is no longer running on it. Children of the
+/// process are handed over to init.
+pub unsafe fn exit(status: usize) -> ! {
+    let (pid, page_dir) = {
+        let mut scheduler = cpu_scheduler().lock();
+        let process = scheduler
+            .current_process
+            .as_mut()
+            .expect("[FATAL] No process to exit");
+
+        (process.pid, process.pgdir.take())
+    };
+
+    if pid == INIT_PID {
+        panic!("[FATAL] Init exited with status {}", status);
+    }
+
+    if let Some(page_dir) = page_dir {
+        free_page_directory(page_dir);
+    }
+
+    // Children running on other processors are not in the process list. Holding the list keeps
+    // them from moving between the two meanwhile.
+    {
+        let mut process_list = PROCESS_LIST.lock();
+        let adopt = |process: &mut Process| {
+            if process.parent == Some(pid) {
+                process.parent = Some(INIT_PID);
+            }
+        };
+
+        process_list.iter_mut().for_each(adopt);
+
+        for (_, cpu) in started_cpus() {
+            cpu.scheduler
+                .lock()
+                .current_process
+                .iter_mut()
+                .for_each(adopt);
+        }
+    }
+
+    {
+        let mut scheduler = cpu_scheduler().lock();
+        let process = scheduler.current_process.as_mut().unwrap();
+        process.state = ProcessState::ZOMBIE;
+        process.exit_status = status;
+    }
+
+    // Both the parent and init (if it adopted zombies) are woken up by the scheduler
+    sched();
+
+    panic!("[FATAL] Zombie process was scheduled");
+}
+
+/// Wait for a child of the current process to exit, either a specific one or (without a PID)
+/// any of them. The zombie child is removed for good, and its PID and exit status are
+/// returned. Fails right away if there is no such child.
+pub unsafe fn wait(pid: Option<usize>) -> Result<(usize, usize), &'static str> {
+    let parent_pid = cpu_scheduler()
+        .lock()
+        .current_process
+        .as_ref()
+        .ok_or("[ERR] No process to wait")?
+        .pid;
+
+    let is_awaited = |process: &Process| {
+        process.parent == Some(parent_pid) && pid.map_or(true, |pid| pid == process.pid)
+    };
+
+    let mut process_list = PROCESS_LIST.lock();
+
+    loop {
+        // Children running on other processors are not in the process list
+        let running = started_cpus().any(|(_, cpu)| {
+            let scheduler = cpu.scheduler.lock();
+            scheduler.current_process.as_ref().map_or(false, is_awaited)
+        });
+
+        if !running && !process_list.iter().any(is_awaited) {
+            return Err("[ERR] No children to wait for");
+        }
+
+        let zombie = process_list
+            .iter()
+            .position(|process| is_awaited(process) && process.state == ProcessState::ZOMBIE);
+
+        if let Some(index) = zombie {
+            let child = process_list.remove(index).unwrap();
+            return Ok((child.pid, child.exit_status));
+        }
+
+        process_list = sleep(exit_channel(), process_list);
+    }
+}
@@ -1,16 +1,54 @@
-use spin::Mutex;
+use alloc::collections::VecDeque;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::{
-    scheduler::process::switch_user_virtual_memory, structures::heap_linked_list::HeapLinkedList,
+    interrupts::intrpt,
+    memory::{
+        defs::Page,
+        vm::{deallocate_page, kernel_page_dir},
+    },
+    scheduler::process::{exit_channel, switch_user_virtual_memory},
+    smp::cpu::{started_cpus, this_cpu},
+    threading::defs::{SpinLock, SpinLockGuard},
+    x86::helpers::hlt,
 };
 
 use super::defs::{
-    process::{Process, ProcessState, TrapFrame},
-    scheduler::Scheduler,
+    process::{Context, Process, ProcessState, TrapFrame},
+    scheduler::{Scheduler, DEFAULT_TIME_SLICE},
 };
 
-pub static mut PROCESS_LIST: Mutex<HeapLinkedList<Process>> = Mutex::new(HeapLinkedList::new());
-pub static mut SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler::new());
+/// Processes ready to run, in the order they will be scheduled. Shared by every processor.
+pub static mut PROCESS_LIST: SpinLock<VecDeque<Process>> = SpinLock::new(VecDeque::new());
+
+/// Number of timer ticks a process runs for before being preempted
+static TIME_SLICE: AtomicUsize = AtomicUsize::new(DEFAULT_TIME_SLICE);
+
+/// Set the number of timer ticks a process runs for before being preempted. A slice is at
+/// least one tick long, and processes already running pick it up on their next tick.
+pub fn set_time_slice(ticks: usize) {
+    TIME_SLICE.store(ticks.max(1), Ordering::SeqCst);
+}
+
+/// Number of timer ticks a process runs for before being preempted
+pub fn time_slice() -> usize {
+    TIME_SLICE.load(Ordering::SeqCst)
+}
+
+/// Scheduler of the running processor. A process in the Kernel is not preempted, so it keeps
+/// running on the same processor until it gives the CPU back.
+pub fn cpu_scheduler() -> &'static SpinLock<Scheduler> {
+    &this_cpu().scheduler
+}
+
+/// PID of the process running on this processor, if any
+pub fn current_pid() -> Option<usize> {
+    cpu_scheduler()
+        .lock()
+        .current_process
+        .as_ref()
+        .map(|process| process.pid)
+}
 
 extern "C" {
     fn switch(scheduler_context: usize, process_context: usize);
@@ -21,6 +59,7 @@ impl Scheduler {
         Scheduler {
             current_process: None,
             context: 0,
+            ticks: 0,
         }
     }
 
@@ -38,11 +77,25 @@ impl Scheduler {
         }
     }
 
+    /// Run the next ready process until it gives the CPU back (e.g. once its time slice is
+    /// over or it goes to sleep), and then place it at the back of the queue.
     pub fn schedule(&mut self) -> Option<()> {
-        let mut process = unsafe { PROCESS_LIST.lock().pop()? };
-        let mut process_context = process.context.expect("[FATAL] No Context");
-        process.state = ProcessState::RUNNING;
-        self.current_process = Some(process);
+        // The current process only changes with the process list held, as wakeup looks for
+        // sleepers in both places
+        let process_context = unsafe {
+            let mut process_list = PROCESS_LIST.lock();
+            let index = process_list
+                .iter()
+                .position(|process| process.state == ProcessState::READY)?;
+
+            let mut process = process_list.remove(index)?;
+            process.state = ProcessState::RUNNING;
+
+            let process_context = process.context.expect("[FATAL] No Context");
+            self.current_process = Some(process);
+            process_context
+        };
+        self.ticks = 0;
 
         unsafe {
             switch_user_virtual_memory(self.current_process.as_ref().unwrap());
@@ -53,28 +106,164 @@ impl Scheduler {
             );
         };
 
+        // Back in the scheduler: the process gave the CPU back
+        kernel_page_dir().activate();
+
+        let mut process_list = unsafe { PROCESS_LIST.lock() };
+
+        if let Some(mut process) = self.current_process.take() {
+            match process.state {
+                ProcessState::RUNNING => process.state = ProcessState::READY,
+                // The process exited, and is no longer running on its kernel stack
+                ProcessState::ZOMBIE => {
+                    if let Some(kernel_stack) = process.kernel_stack.take() {
+                        deallocate_page(Page {
+                            address: kernel_stack,
+                        });
+                    }
+
+                    process.context = None;
+                    process.trapframe = None;
+
+                    // Only now can the parent find the zombie in the process list
+                    wakeup_locked(&mut process_list, exit_channel());
+                }
+                _ => {}
+            }
+
+            process_list.push_back(process);
+        }
+
         Some(())
     }
 
+    /// Account a timer tick to the current process. Returns whether its time slice is over.
+    pub fn tick(&mut self) -> bool {
+        if self.current_process.is_none() {
+            return false;
+        }
+
+        self.ticks += 1;
+        self.ticks >= time_slice()
+    }
+
+    /// Where the context of the current process is saved when it switches to the scheduler
+    fn context_slot(&mut self) -> Option<usize> {
+        let process = self.current_process.as_mut()?;
+        let context = process.context.as_mut().expect("[FATAL] No Context");
+
+        Some(context as *mut *mut Context as usize)
+    }
+
     /// Main execution loop. If there is a process to schedule and this CPU is not currently
     /// busy running another process, takes the next process and schedule it.
     pub fn run(&mut self) {
         unsafe {
-            // At this moment, SCHEDULER has been locked so any future access is prohibited.
+            // At this moment, the scheduler has been locked so any future access is prohibited.
             // At this point we can unlock it for future use.
-            SCHEDULER.force_unlock();
+            cpu_scheduler().force_unlock();
         }
 
         loop {
-            if self.current_process.is_none() {
-                self.schedule();
+            // Interrupt handlers may touch the process list, so keep them away while scheduling
+            intrpt::disable();
+
+            if self.schedule().is_none() {
+                // Nothing to run: wait for an interrupt to make a process ready
+                intrpt::enable();
+                hlt();
             }
         }
     }
 }
 
-pub fn setup_scheduler() {
-    unsafe {
-        SCHEDULER.lock().run();
+/// Give the CPU back to the scheduler. The context of the current process is saved in its own
+/// kernel stack, and it resumes from here once scheduled again. The state of the process must
+/// be updated beforehand, as it tells the scheduler what to do with the process. Notice no lock
+/// may be held while switching: the scheduler needs them, and the process may resume on another
+/// processor.
+pub fn sched() {
+    if this_cpu().interrupt_depth.load(Ordering::SeqCst) != 0 {
+        panic!("[FATAL] Switching to the Scheduler With a Lock Held");
     }
+
+    let (process_context, scheduler_context) = {
+        let mut scheduler = cpu_scheduler().lock();
+
+        match scheduler.context_slot() {
+            Some(process_context) => (process_context, scheduler.context),
+            None => return,
+        }
+    };
+
+    unsafe { switch(process_context, scheduler_context) };
+}
+
+/// Preempt the current process if its time slice is over
+pub fn preempt() {
+    let expired = cpu_scheduler().lock().tick();
+
+    if expired {
+        sched();
+    }
+}
+
+/// Put the current process to sleep on the given channel (any address identifying what the
+/// process waits for) until wakeup is called on it. The lock guarding the condition the process
+/// waits for is released while sleeping, and taken again before returning. As the process is
+/// asleep before the lock is released, a wakeup sent right after can not be missed. Callers
+/// must check their condition again once woken up. Without a current process (e.g. during
+/// boot), waits for the next interrupt instead.
+#[track_caller]
+pub fn sleep<'a, T: ?Sized>(channel: usize, guard: SpinLockGuard<'a, T>) -> SpinLockGuard<'a, T> {
+    let mut scheduler = cpu_scheduler().lock();
+
+    match scheduler.current_process.as_mut() {
+        Some(process) => {
+            process.state = ProcessState::SLEEPING;
+            process.channel = Some(channel);
+        }
+        None => {
+            drop(scheduler);
+            let lock = SpinLockGuard::unlock(guard);
+            hlt();
+            return lock.lock();
+        }
+    }
+
+    let lock = SpinLockGuard::unlock(guard);
+    drop(scheduler);
+
+    sched();
+    lock.lock()
+}
+
+/// Mark every process sleeping on the given channel as ready
+fn wakeup_processes<'a>(processes: impl Iterator<Item = &'a mut Process>, channel: usize) {
+    for process in processes {
+        if process.state == ProcessState::SLEEPING && process.channel == Some(channel) {
+            process.state = ProcessState::READY;
+            process.channel = None;
+        }
+    }
+}
+
+/// Like wakeup, with the process list already held. A process which just went to sleep may
+/// still be held by its processor, until the processor switches back to its scheduler and
+/// places it in the process list.
+fn wakeup_locked(process_list: &mut VecDeque<Process>, channel: usize) {
+    wakeup_processes(process_list.iter_mut(), channel);
+
+    for (_, cpu) in started_cpus() {
+        wakeup_processes(cpu.scheduler.lock().current_process.iter_mut(), channel);
+    }
+}
+
+/// Wake up every process sleeping on the given channel
+pub fn wakeup(channel: usize) {
+    wakeup_locked(unsafe { &mut PROCESS_LIST.lock() }, channel);
+}
+
+pub fn setup_scheduler() {
+    cpu_scheduler().lock().run();
 }
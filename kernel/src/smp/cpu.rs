@@ -0,0 +1,106 @@
+/// Every processor has its own GDT, TSS and scheduler, kept in CPUS. The GS segment of each
+/// processor starts at its own entry, so a processor finds its entry by reading its index at
+/// GS:0. Until its GDT is loaded, the running processor is assumed to be the BSP.
+use core::{
+    mem::offset_of,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use spin::Mutex;
+
+use super::defs::*;
+use crate::{
+    interrupts::mp::MP_CONFIGURATION,
+    memory::defs::{GlobalDescriptorTable, TaskStateSegment, CPU_SEG_ENTRY},
+    scheduler::defs::scheduler::Scheduler,
+    threading::defs::SpinLock,
+    x86::helpers::{read_gs, read_gs_offset},
+};
+
+pub static CPUS: [Cpu; MAX_CPUS] = {
+    let mut cpus = [const { Cpu::new() }; MAX_CPUS];
+    let mut id = 0;
+
+    while id < MAX_CPUS {
+        cpus[id].id = id;
+        id += 1;
+    }
+
+    cpus
+};
+
+/// Processors listed in CPUS, whether started or not
+static CPU_COUNT: AtomicUsize = AtomicUsize::new(1);
+
+impl Cpu {
+    pub const fn new() -> Self {
+        Cpu {
+            id: 0,
+            apic_id: AtomicUsize::new(0),
+            started: AtomicBool::new(false),
+            scheduler: SpinLock::new(Scheduler::new()),
+            gdt: Mutex::new(GlobalDescriptorTable::new()),
+            tss: Mutex::new(TaskStateSegment::new()),
+            interrupt_depth: AtomicUsize::new(0),
+            interrupts_enabled: AtomicBool::new(false),
+        }
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.started.load(Ordering::SeqCst)
+    }
+}
+
+pub fn cpu_count() -> usize {
+    CPU_COUNT.load(Ordering::SeqCst)
+}
+
+/// Index (in CPUS) of the running processor
+pub fn cpu_id() -> usize {
+    if read_gs() != CPU_SEG_ENTRY << 3 {
+        return BOOT_CPU;
+    }
+
+    read_gs_offset(offset_of!(Cpu, id))
+}
+
+pub fn this_cpu() -> &'static Cpu {
+    &CPUS[cpu_id()]
+}
+
+pub fn is_boot_cpu() -> bool {
+    cpu_id() == BOOT_CPU
+}
+
+/// Processors running the Kernel, along with their index
+pub fn started_cpus() -> impl Iterator<Item = (usize, &'static Cpu)> {
+    CPUS[..cpu_count()]
+        .iter()
+        .enumerate()
+        .filter(|(_, cpu)| cpu.is_started())
+}
+
+/// Fill CPUS with the processors listed in the MP tables, the BSP first. Without MP tables,
+/// the BSP is the only processor.
+pub fn setup_cpus() {
+    let boot_cpu = &CPUS[BOOT_CPU];
+
+    if let Some(config) = *MP_CONFIGURATION.lock() {
+        let application_processors = config
+            .processors()
+            .iter()
+            .filter(|&&apic_id| apic_id != config.bsp_apic_id);
+
+        boot_cpu
+            .apic_id
+            .store(config.bsp_apic_id as usize, Ordering::SeqCst);
+
+        for (cpu, &apic_id) in CPUS[BOOT_CPU + 1..].iter().zip(application_processors) {
+            cpu.apic_id.store(apic_id as usize, Ordering::SeqCst);
+        }
+
+        CPU_COUNT.store(config.num_processors, Ordering::SeqCst);
+    }
+
+    boot_cpu.started.store(true, Ordering::SeqCst);
+}
@@ -0,0 +1,51 @@
+use core::sync::atomic::{AtomicBool, AtomicUsize};
+
+use spin::Mutex;
+
+use crate::{
+    memory::defs::{GlobalDescriptorTable, TaskStateSegment},
+    scheduler::defs::scheduler::Scheduler,
+    threading::defs::SpinLock,
+};
+
+pub use crate::interrupts::defs::mp::MAX_CPUS;
+
+pub const BOOT_CPU: usize = 0; // The BSP always comes first in CPUS
+
+/// Physical address the APs start executing at (see boot/ap_entry.asm). Must be page aligned
+/// and below 1MiB.
+pub const AP_TRAMPOLINE: usize = 0x7000;
+pub const AP_STACK_PAGES: usize = 4;
+pub const AP_STARTUP_TIMEOUT_MS: usize = 100; // How long to wait for an AP to report running
+
+/// CMOS shutdown status and BIOS warm reset vector, telling the BIOS where to jump after INIT
+pub const CMOS_ADDRESS: u16 = 0x70;
+pub const CMOS_DATA: u16 = 0x71;
+pub const CMOS_SHUTDOWN_STATUS: u8 = 0x0F;
+pub const CMOS_WARM_RESET: u8 = 0x0A;
+pub const WARM_RESET_VECTOR: usize = 0x467;
+
+/// State private to each processor, reached through the GS segment (see cpu.rs). The scheduler
+/// holds the current process and the context to switch back to the scheduler. The GDT and TSS
+/// are locked while the GDT is being loaded, before this_cpu() can find the processor, so they
+/// cannot be SpinLocks.
+#[repr(C)]
+pub struct Cpu {
+    pub id: usize, // Index in CPUS, must come first (read through GS)
+    pub apic_id: AtomicUsize,
+    pub started: AtomicBool,
+    pub scheduler: SpinLock<Scheduler>,
+    pub gdt: Mutex<GlobalDescriptorTable>,
+    pub tss: Mutex<TaskStateSegment>,
+    pub interrupt_depth: AtomicUsize, // Nesting depth of interrupt disabling
+    pub interrupts_enabled: AtomicBool, // Were interrupts enabled before being disabled?
+}
+
+/// Parameters of the AP trampoline, placed right below AP_TRAMPOLINE
+#[repr(C)]
+pub struct ApStartup {
+    pub kernel_page_dir: u32, // Physical address
+    pub boot_page_dir: u32,   // Physical address
+    pub entry: u32,
+    pub stack: u32, // Top of the Kernel stack
+}
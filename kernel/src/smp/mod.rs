@@ -0,0 +1,3 @@
+pub mod cpu;
+pub mod defs;
+pub mod startup;
@@ -0,0 +1,135 @@
+/// Application Processor (AP) bring-up. The BSP copies the trampoline (boot/ap_entry.asm) below
+/// 1MiB, prepares a Kernel stack for each AP, and wakes them up one at a time through their
+/// Local APIC. Once running, every AP loads its own GDT and TSS, enables its Local APIC and
+/// timer, and runs its own scheduler, taking processes from the shared run queue.
+/// More information can be found here: https://wiki.osdev.org/Symmetric_Multiprocessing
+use core::{
+    mem::size_of,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::{
+    cpu::{cpu_count, cpu_id, setup_cpus, this_cpu, CPUS},
+    defs::*,
+};
+use crate::{
+    devices::timer::{frequency, pit_delay_ms},
+    interrupts::{
+        apic::apic_enabled,
+        idt::load_idt,
+        lapic::{set_timer_frequency, setup_lapic, start_ap},
+        mp::MP_CONFIGURATION,
+    },
+    memory::{
+        defs::{KERNEL_BASE, PAGE_SIZE},
+        frame::allocate_frames,
+        gdt::load_gdt,
+        mem::memmove,
+        vm::kernel_page_dir,
+    },
+    println,
+    x86::helpers::outb,
+    P2V, V2P,
+};
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_end: u8;
+    static boot_page_dir: u8;
+}
+
+/// Index (in CPUS) of the AP being started
+static STARTING_CPU: AtomicUsize = AtomicUsize::new(BOOT_CPU);
+
+/// First Rust code run by the APs, on the stack prepared by the BSP
+extern "C" fn ap_main() -> ! {
+    load_gdt(STARTING_CPU.load(Ordering::SeqCst));
+    load_idt();
+
+    let lapic_address = MP_CONFIGURATION
+        .lock()
+        .expect("[FATAL] AP Without MP Tables")
+        .lapic_address;
+
+    setup_lapic(lapic_address);
+    set_timer_frequency(frequency());
+
+    println!("[KERNEL] CPU {} Started", cpu_id());
+    this_cpu().started.store(true, Ordering::SeqCst);
+
+    this_cpu().scheduler.lock().run();
+    panic!("[FATAL] Returned from Scheduler");
+}
+
+/// Copy the trampoline to AP_TRAMPOLINE, and tell the BIOS to jump there after an INIT
+unsafe fn setup_trampoline() {
+    let start = &ap_trampoline_start as *const u8 as usize;
+    let end = &ap_trampoline_end as *const u8 as usize;
+
+    memmove(start, P2V!(AP_TRAMPOLINE), end - start);
+
+    outb(CMOS_ADDRESS, CMOS_SHUTDOWN_STATUS);
+    outb(CMOS_DATA, CMOS_WARM_RESET);
+
+    let warm_reset_vector = P2V!(WARM_RESET_VECTOR) as *mut u16;
+    warm_reset_vector.write_volatile(0);
+    warm_reset_vector
+        .add(1)
+        .write_volatile((AP_TRAMPOLINE >> 4) as u16);
+}
+
+/// Start the AP at the given index of CPUS, and wait for it to report running
+unsafe fn start_cpu(id: usize) -> Result<(), &'static str> {
+    let cpu = &CPUS[id];
+    let stack = allocate_frames(AP_STACK_PAGES)?;
+    let startup = (P2V!(AP_TRAMPOLINE) - size_of::<ApStartup>()) as *mut ApStartup;
+
+    startup.write_volatile(ApStartup {
+        kernel_page_dir: kernel_page_dir().physical_address() as u32,
+        boot_page_dir: V2P!(&boot_page_dir as *const u8 as usize) as u32,
+        entry: ap_main as extern "C" fn() -> ! as usize as u32,
+        stack: (stack.address as usize + AP_STACK_PAGES * PAGE_SIZE) as u32,
+    });
+
+    STARTING_CPU.store(id, Ordering::SeqCst);
+    start_ap(cpu.apic_id.load(Ordering::SeqCst) as u8, AP_TRAMPOLINE);
+
+    // The parameters are reused by the next AP, so it must be up and running first
+    for _ in 0..AP_STARTUP_TIMEOUT_MS {
+        if cpu.is_started() {
+            return Ok(());
+        }
+
+        pit_delay_ms(1);
+    }
+
+    Err("[ERR] AP Did Not Start")
+}
+
+/// Register the processors and start every AP. Must run on the BSP, once the Kernel page
+/// directory, the IDT and the Local APIC are set up.
+pub fn setup_smp() {
+    setup_cpus();
+
+    if !apic_enabled() || cpu_count() == 1 {
+        println!("[KERNEL] Running on a single CPU");
+        return;
+    }
+
+    unsafe { setup_trampoline() };
+
+    for id in BOOT_CPU + 1..cpu_count() {
+        // A late AP would pick up the parameters of the next one, so stop here
+        if let Err(error) = unsafe { start_cpu(id) } {
+            println!("{} - CPU {}", error, id);
+            break;
+        }
+    }
+
+    let started = CPUS[..cpu_count()]
+        .iter()
+        .filter(|cpu| cpu.is_started())
+        .count();
+
+    println!("[KERNEL] SMP Initialized: {} CPU(s) Running", started);
+}
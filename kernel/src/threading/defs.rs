@@ -0,0 +1,43 @@
+use core::{
+    cell::UnsafeCell,
+    panic::Location,
+    sync::atomic::{AtomicBool, AtomicUsize},
+};
+
+/// Spin Lock Constants (spin.rs)
+pub mod spin {
+    pub const NO_OWNER: usize = usize::MAX;
+}
+
+/// Mutual exclusion lock, busy waiting until it is free. Interrupts are disabled on the
+/// holding processor, so an interrupt handler never spins on a lock its own processor holds.
+pub struct SpinLock<T: ?Sized> {
+    pub(super) locked: AtomicBool,
+    pub(super) owner: AtomicUsize, // Index (in CPUS) of the holding processor
+    pub(super) location: UnsafeCell<Option<&'static Location<'static>>>, // Acquire site
+    pub(super) data: UnsafeCell<T>,
+}
+
+/// Access to the data of a locked SpinLock. The lock is released once dropped.
+pub struct SpinLockGuard<'a, T: ?Sized + 'a> {
+    pub(super) lock: &'a SpinLock<T>,
+}
+
+/// State of a SleepLock, guarded by a SpinLock
+pub struct SleepLockState {
+    pub locked: bool,
+    pub pid: Option<usize>, // Process holding the lock (None if taken outside a process)
+}
+
+/// Mutual exclusion lock which may be held for a long time, e.g. across disk I/O. Waiters
+/// sleep instead of spinning, and interrupts stay enabled while it is held. As holders may
+/// sleep, it must not be taken with a SpinLock held, nor in interrupt handlers.
+pub struct SleepLock<T: ?Sized> {
+    pub(super) state: SpinLock<SleepLockState>,
+    pub(super) data: UnsafeCell<T>,
+}
+
+/// Access to the data of a locked SleepLock. The lock is released once dropped.
+pub struct SleepLockGuard<'a, T: ?Sized + 'a> {
+    pub(super) lock: &'a SleepLock<T>,
+}
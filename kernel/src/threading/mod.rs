@@ -1 +1,3 @@
+pub mod defs;
+pub mod sleep;
 pub mod spin;
@@ -0,0 +1,81 @@
+/// Sleep locks, xv6 style. A SpinLock guards the state of the lock, and processes waiting for
+/// it sleep on the lock itself (its address is the sleeping channel) until the holder releases
+/// it. Meant for resources held across operations which block, such as disk buffers.
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+};
+
+use super::defs::{SleepLock, SleepLockGuard, SleepLockState, SpinLock};
+use crate::scheduler::scheduler::{current_pid, sleep, wakeup};
+
+unsafe impl<T: ?Sized + Send> Sync for SleepLock<T> {}
+unsafe impl<T: ?Sized + Send> Send for SleepLock<T> {}
+
+impl<T> SleepLock<T> {
+    pub const fn new(data: T) -> Self {
+        SleepLock {
+            state: SpinLock::new(SleepLockState {
+                locked: false,
+                pid: None,
+            }),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T: ?Sized> SleepLock<T> {
+    /// Sleeping channel of the processes waiting for the lock
+    fn channel(&self) -> usize {
+        self as *const Self as *const u8 as usize
+    }
+
+    /// Whether the current process holds the lock
+    pub fn holding(&self) -> bool {
+        let state = self.state.lock();
+        state.locked && state.pid == current_pid()
+    }
+
+    /// Sleep until the lock is free, and take it
+    #[track_caller]
+    pub fn lock(&self) -> SleepLockGuard<'_, T> {
+        let mut state = self.state.lock();
+
+        if state.locked && state.pid.is_some() && state.pid == current_pid() {
+            panic!("[FATAL] Recursive Sleep Lock Acquisition");
+        }
+
+        while state.locked {
+            state = sleep(self.channel(), state);
+        }
+
+        state.locked = true;
+        state.pid = current_pid();
+
+        SleepLockGuard { lock: self }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for SleepLockGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for SleepLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for SleepLockGuard<'a, T> {
+    fn drop(&mut self) {
+        let mut state = self.lock.state.lock();
+
+        state.locked = false;
+        state.pid = None;
+        wakeup(self.lock.channel());
+    }
+}
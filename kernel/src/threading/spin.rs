@@ -1,17 +1,137 @@
-// /// A guard that provides mutable data access.
-// ///
-// /// When the guard falls out of scope it will release the lock.
-// pub struct SpinMutexGuard<'a, T: ?Sized + 'a> {
-//     lock: &'a AtomicBool,
-//     data: *mut T,
-// }
-
-// pub struct SpinMutex<T: ?Sized, R = Spin> {
-//     phantom: PhantomData<R>,
-//     pub(crate) lock: AtomicBool,
-//     data: UnsafeCell<T>,
-// }
-
-// pub struct Mutex<T: ?Sized, R = Spin> {
-//     inner: InnerMutex<T, R>,
-// }
+/// Spin locks, xv6 style. Interrupts stay disabled (through push_disable) while a lock is held,
+/// as an interrupt handler spinning on a lock held by the code it interrupted would never get
+/// it. Every lock records the processor holding it and where it was taken, so taking a lock
+/// twice on the same processor panics instead of deadlocking.
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    panic::Location,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use super::defs::{spin::NO_OWNER, SpinLock, SpinLockGuard};
+use crate::{
+    interrupts::intrpt::{pop_disable, push_disable},
+    smp::cpu::cpu_id,
+};
+
+unsafe impl<T: ?Sized + Send> Sync for SpinLock<T> {}
+unsafe impl<T: ?Sized + Send> Send for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    pub const fn new(data: T) -> Self {
+        SpinLock {
+            locked: AtomicBool::new(false),
+            owner: AtomicUsize::new(NO_OWNER),
+            location: UnsafeCell::new(None),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T: ?Sized> SpinLock<T> {
+    /// Whether the running processor holds the lock. Interrupts must be disabled, otherwise
+    /// the answer may be stale by the time it is used.
+    pub fn holding(&self) -> bool {
+        self.locked.load(Ordering::SeqCst) && self.owner.load(Ordering::SeqCst) == cpu_id()
+    }
+
+    /// Mark the lock as taken by the running processor, from the given site
+    fn acquired(&self, location: &'static Location<'static>) -> SpinLockGuard<'_, T> {
+        self.owner.store(cpu_id(), Ordering::SeqCst);
+        unsafe { *self.location.get() = Some(location) };
+
+        SpinLockGuard { lock: self }
+    }
+
+    /// Spin until the lock is free, and take it. Interrupts stay disabled until it is released.
+    #[track_caller]
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        let location = Location::caller();
+        push_disable();
+
+        if self.holding() {
+            match unsafe { *self.location.get() } {
+                Some(holder) => panic!(
+                    "[FATAL] Recursive Lock Acquisition at {} - Held Since {}",
+                    location, holder
+                ),
+                None => panic!("[FATAL] Recursive Lock Acquisition at {}", location),
+            }
+        }
+
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            while self.locked.load(Ordering::Relaxed) {
+                core::hint::spin_loop();
+            }
+        }
+
+        self.acquired(location)
+    }
+
+    /// Take the lock if it is free, without spinning
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        let location = Location::caller();
+        push_disable();
+
+        if self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            return Some(self.acquired(location));
+        }
+
+        pop_disable();
+        None
+    }
+
+    /// Release the lock without a guard, e.g. when the guard was leaked (mem::forget) or lives
+    /// in a context that never returns.
+    ///
+    /// # Safety
+    /// The lock must be held by the calling processor, and no guard of it may be used (or
+    /// dropped) after this call, as the data it points to is no longer protected.
+    pub unsafe fn force_unlock(&self) {
+        *self.location.get() = None;
+        self.owner.store(NO_OWNER, Ordering::SeqCst);
+        self.locked.store(false, Ordering::Release);
+
+        pop_disable();
+    }
+}
+
+impl<'a, T: ?Sized> SpinLockGuard<'a, T> {
+    /// Release the lock, handing it back so it can be taken again later (see sleep)
+    pub fn unlock(guard: Self) -> &'a SpinLock<T> {
+        let lock = guard.lock;
+        drop(guard);
+
+        lock
+    }
+}
+
+impl<'a, T: ?Sized> Deref for SpinLockGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for SpinLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for SpinLockGuard<'a, T> {
+    fn drop(&mut self) {
+        unsafe { self.lock.force_unlock() };
+    }
+}
@@ -14,3 +14,13 @@ pub enum PrivilegeLevel {
 
 pub type ShortSegmentDescriptor = u64;
 pub type LongSegmentDescriptor = u128;
+
+// ************* Control Registers *************
+
+/// Write Protect. When set, the Kernel also faults when writing to read-only user pages
+pub const CR0_WRITE_PROTECT: usize = 1 << 16;
+
+// ****************** EFLAGS ******************
+
+/// Interrupt Enable Flag. User processes run with it set, so the timer can preempt them
+pub const EFLAGS_INTERRUPT_ENABLE: usize = 1 << 9;
@@ -4,6 +4,22 @@ use crate::{interrupts::defs::InterruptDescriptorTablePointer, memory::defs::Glo
 
 // ******** Control Registers ********
 
+#[inline]
+pub fn load_cr0(value: usize) {
+    unsafe {
+        asm!("mov cr0, {}", in(reg) value, options(nostack, preserves_flags));
+    }
+}
+
+#[inline]
+pub fn read_cr0() -> usize {
+    unsafe {
+        let mut value: usize = 0;
+        asm!("mov {}, cr0", out(reg) value, options(nostack, preserves_flags));
+        value
+    }
+}
+
 #[inline]
 pub fn load_cr3(page_dir: usize) {
     unsafe {
@@ -20,6 +36,14 @@ pub fn read_cr3() -> usize {
     }
 }
 
+/// Invalidate the TLB entry of the page holding the given virtual address
+#[inline]
+pub fn invlpg(address: usize) {
+    unsafe {
+        asm!("invlpg [{}]", in(reg) address, options(nostack, preserves_flags));
+    }
+}
+
 /// Cause a breakpoint exception by invoking the `int3` instruction.
 #[inline]
 pub fn read_cr2() -> usize {
@@ -39,6 +63,16 @@ pub fn lidt(idt: &InterruptDescriptorTablePointer) {
     }
 }
 
+/// Read the EFLAGS register
+#[inline]
+pub fn read_eflags() -> usize {
+    unsafe {
+        let value: usize;
+        asm!("pushfd", "pop {}", out(reg) value, options(nomem, preserves_flags));
+        value
+    }
+}
+
 #[inline]
 pub fn cli() {
     unsafe {
@@ -84,7 +118,26 @@ pub fn load_cs(sel: u16) {
 #[inline]
 pub fn set_gs(v: u16) {
     unsafe {
-        asm!("gs {0:x}", in(reg) v, options(readonly, nostack, preserves_flags));
+        asm!("mov gs, {0:x}", in(reg) v, options(nomem, nostack, preserves_flags));
+    }
+}
+
+#[inline]
+pub fn read_gs() -> u16 {
+    unsafe {
+        let value: u16;
+        asm!("mov {0:x}, gs", out(reg) value, options(nomem, nostack, preserves_flags));
+        value
+    }
+}
+
+/// Read the 32-bit value at the given offset of the GS segment
+#[inline]
+pub fn read_gs_offset(offset: usize) -> usize {
+    unsafe {
+        let value: usize;
+        asm!("mov {0}, gs:[{1}]", out(reg) value, in(reg) offset, options(readonly, nostack, preserves_flags));
+        value
     }
 }
 
@@ -163,3 +216,11 @@ pub fn int3() {
         asm!("int3", options(nomem, nostack));
     }
 }
+
+/// Stop the CPU until the next interrupt arrives, by invoking the `hlt` instruction.
+#[inline]
+pub fn hlt() {
+    unsafe {
+        asm!("hlt", options(nomem, nostack, preserves_flags));
+    }
+}
@@ -1,7 +1,14 @@
 #![no_std]
 #![no_main]
 
-use 
+// Brings in the panic handler
+use buzzos_user_space as _;
 
 /// Init main
 fn init_process() {}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    init_process();
+    loop {}
+}
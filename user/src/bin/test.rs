@@ -7,6 +7,12 @@ fn init_process() {
     loop {}
 }
 
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    init_process();
+    loop {}
+}
+
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
     loop {}
@@ -0,0 +1,72 @@
+/// System Call wrappers. Arguments are passed following the ABI described in abi::syscall, and
+/// failures come back as an Errno.
+use abi::{
+    errno::{Errno, SystemCallResult},
+    syscall::*,
+};
+use core::arch::asm;
+
+/// Issue a System Call with up to four arguments. esi is reserved by LLVM, so it is saved and
+/// loaded by hand.
+pub unsafe fn syscall(
+    number: usize,
+    arg0: usize,
+    arg1: usize,
+    arg2: usize,
+    arg3: usize,
+) -> SystemCallResult {
+    let result: usize;
+
+    asm!(
+        "push esi",
+        "mov esi, {arg1}",
+        "int {vector}",
+        "pop esi",
+        arg1 = in(reg) arg1,
+        vector = const SYSCALL_VECTOR,
+        inlateout("eax") number => result,
+        in("edi") arg0,
+        in("edx") arg2,
+        in("ecx") arg3,
+    );
+
+    Errno::from_return_value(result)
+}
+
+pub fn print_trapframe() -> SystemCallResult {
+    unsafe { syscall(PRINT_TRAPFRAME_SYSCALL, 0, 0, 0, 0) }
+}
+
+/// Returns the PID of the child in the parent, and 0 in the child
+pub fn fork() -> SystemCallResult {
+    unsafe { syscall(FORK_SYSCALL, 0, 0, 0, 0) }
+}
+
+/// Path and arguments must be NUL-terminated, and argv must end with a NULL pointer. Only
+/// returns on failure.
+pub unsafe fn exec(path: *const u8, argv: *const *const u8) -> SystemCallResult {
+    syscall(EXEC_SYSCALL, path as usize, argv as usize, 0, 0)
+}
+
+pub fn exit(status: usize) -> ! {
+    unsafe { syscall(EXIT_SYSCALL, status, 0, 0, 0).ok() };
+    unreachable!()
+}
+
+/// Wait for any child to exit, returning its PID and exit status
+pub fn wait() -> Result<(usize, usize), Errno> {
+    let mut status = 0;
+    let pid = unsafe { syscall(WAIT_SYSCALL, &mut status as *mut usize as usize, 0, 0, 0)? };
+
+    Ok((pid, status))
+}
+
+/// Wait for the given child (any child if None) to exit, returning its PID and exit status
+pub fn waitpid(pid: Option<usize>) -> Result<(usize, usize), Errno> {
+    let mut status = 0;
+    let pid = pid.unwrap_or(usize::MAX);
+    let status_address = &mut status as *mut usize as usize;
+    let pid = unsafe { syscall(WAITPID_SYSCALL, pid, status_address, 0, 0)? };
+
+    Ok((pid, status))
+}